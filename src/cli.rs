@@ -0,0 +1,371 @@
+use anyhow::{Result, bail};
+use clap::{Args, Parser, Subcommand, ValueEnum, value_parser};
+use hbsx::engine::{FlushPolicy, OrphanPolicy};
+use hbsx::keys::{
+    ARGON2_ITERATIONS, ARGON2_MAX_ITERATIONS, ARGON2_MAX_MEMORY_KIB, ARGON2_MAX_PARALLELISM,
+    ARGON2_MEMORY_KIB, ARGON2_MIN_ITERATIONS, ARGON2_MIN_MEMORY_KIB, ARGON2_MIN_PARALLELISM,
+    ARGON2_PARALLELISM, Kdf, PBKDF2_ITERS, PBKDF2_MAX_ITERATIONS, PBKDF2_MIN_ITERATIONS,
+};
+use hbsx::manifest::ManifestFormat;
+use hbsx::walk::{FileFilter, SymlinkPolicy, parse_duration, parse_size};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// 文件压缩加密工具
+#[derive(Debug, Parser)]
+#[command(name = "hbsx", version, about)]
+pub struct Cli {
+    /// 数据库文件路径（默认为 ~/.xor/data.db）
+    #[arg(long, global = true, env = "XOR_DB")]
+    pub db: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 压缩并加密目录（增量处理）
+    Encrypt(Box<EncryptArgs>),
+    /// 将加密目录还原为原始目录结构
+    Restore(RestoreArgs),
+    /// 校验输出文件与数据库记录是否一致
+    Verify(VerifyArgs),
+    /// 显示数据库中的统计信息
+    Status,
+    /// 显示最近的处理日志
+    Log(LogArgs),
+    /// 清理旧的日志记录
+    Prune(PruneArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct EncryptArgs {
+    /// 输入目录
+    #[arg(short, long, value_parser = existing_dir)]
+    pub input: PathBuf,
+
+    /// 输出目录
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub password: PasswordArgs,
+
+    #[command(flatten)]
+    pub kdf: KdfArgs,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// 将数据库保存在输出目录中（catalog.db），使加密目录自描述
+    #[arg(long)]
+    pub catalog_in_output: bool,
+
+    /// 源文件被删除后如何处理其输出文件
+    #[arg(long, value_enum, default_value_t = OrphanArg::Keep)]
+    pub orphans: OrphanArg,
+
+    /// 符号链接的处理方式
+    #[arg(long, value_enum, default_value_t = SymlinkArg::Skip)]
+    pub symlinks: SymlinkArg,
+
+    /// 加密文件名和目录结构：输出文件以随机 ID 保存在 objects/ 下，原始路径保存在加密索引中
+    #[arg(long, conflicts_with = "catalog_in_output")]
+    pub encrypt_names: bool,
+
+    /// 清单格式，可指定多个（如 csv,json）
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "csv")]
+    pub manifest_format: Vec<ManifestFormatArg>,
+
+    /// 额外生成本次运行的变更清单（manifest-delta.*）
+    #[arg(long)]
+    pub delta_manifest: bool,
+
+    /// 将运行摘要（JSON）写入指定文件
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// 只显示本次会执行的操作，不写入输出文件和数据库记录
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// 以 JSON 输出预演结果（需配合 --dry-run）
+    #[arg(long, requires = "dry_run")]
+    pub json: bool,
+
+    /// 每累积多少条记录（文件记录和日志）写入一次数据库
+    #[arg(long, value_name = "N", default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub flush_every: u64,
+
+    /// 至少每隔多少秒写入一次数据库
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub flush_interval: u64,
+}
+
+impl EncryptArgs {
+    pub fn flush(&self) -> FlushPolicy {
+        FlushPolicy {
+            batch_size: self.flush_every as usize,
+            interval: Duration::from_secs(self.flush_interval),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OrphanArg {
+    /// 保留输出文件
+    Keep,
+    /// 删除输出文件
+    Remove,
+    /// 移动到输出目录下的 .archive/
+    Archive,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SymlinkArg {
+    /// 跟随链接，按指向的文件或目录加密
+    Follow,
+    /// 将链接本身记录到数据库，还原时重新创建
+    Store,
+    /// 跳过链接并记录日志
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ManifestFormatArg {
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl From<ManifestFormatArg> for ManifestFormat {
+    fn from(arg: ManifestFormatArg) -> Self {
+        match arg {
+            ManifestFormatArg::Csv => ManifestFormat::Csv,
+            ManifestFormatArg::Json => ManifestFormat::Json,
+            ManifestFormatArg::Ndjson => ManifestFormat::Ndjson,
+        }
+    }
+}
+
+impl From<OrphanArg> for OrphanPolicy {
+    fn from(arg: OrphanArg) -> Self {
+        match arg {
+            OrphanArg::Keep => OrphanPolicy::Keep,
+            OrphanArg::Remove => OrphanPolicy::Remove,
+            OrphanArg::Archive => OrphanPolicy::Archive,
+        }
+    }
+}
+
+impl From<SymlinkArg> for SymlinkPolicy {
+    fn from(arg: SymlinkArg) -> Self {
+        match arg {
+            SymlinkArg::Follow => SymlinkPolicy::Follow,
+            SymlinkArg::Store => SymlinkPolicy::Store,
+            SymlinkArg::Skip => SymlinkPolicy::Skip,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// 加密目录（encrypt 的输出目录）
+    #[arg(short, long, value_parser = existing_dir)]
+    pub input: PathBuf,
+
+    /// 还原目录
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+/// 密码来源（不再支持通过命令行参数传递密码）
+#[derive(Debug, Args)]
+pub struct PasswordArgs {
+    /// 从文件读取密码（使用第一行）
+    #[arg(long)]
+    pub password_file: Option<PathBuf>,
+
+    /// 从指定环境变量读取密码
+    #[arg(long, default_value = "XOR_PASSWORD")]
+    pub password_env: String,
+}
+
+/// 选择要加密的文件（另外会读取输入目录中各层级的 .xorignore）
+#[derive(Debug, Args)]
+pub struct FilterArgs {
+    /// 只加密匹配的文件（gitignore 语法，相对于输入目录，可指定多次）
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// 排除匹配的文件或目录（gitignore 语法，相对于输入目录，可指定多次）
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// 跳过小于该大小的文件（如 1K、10M）
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// 跳过大于该大小的文件（如 100M、2G）
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// 跳过最近该时间内修改的文件（如 30s、10m、1h、7d）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub min_age: Option<Duration>,
+
+    /// 跳过超过该时间未修改的文件（如 30d）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub max_age: Option<Duration>,
+}
+
+impl FilterArgs {
+    pub fn filter(&self) -> FileFilter {
+        FileFilter {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            min_size: self.min_size,
+            max_size: self.max_size,
+            min_age: self.min_age,
+            max_age: self.max_age,
+        }
+    }
+}
+
+/// 新建密钥文件时使用的密码派生函数。已有密钥文件时沿用其中的参数，
+/// 指定的参数与之不一致时报错
+#[derive(Debug, Args)]
+pub struct KdfArgs {
+    /// 密码派生函数 [默认: argon2id]
+    #[arg(long, value_enum)]
+    pub kdf: Option<KdfKind>,
+
+    /// Argon2id 内存大小（KiB） [默认: 65536]
+    #[arg(
+        long,
+        value_parser = value_parser!(u32).range(i64::from(ARGON2_MIN_MEMORY_KIB)..=i64::from(ARGON2_MAX_MEMORY_KIB))
+    )]
+    pub argon2_memory: Option<u32>,
+
+    /// Argon2id 迭代次数 [默认: 3]
+    #[arg(
+        long,
+        value_parser = value_parser!(u32).range(i64::from(ARGON2_MIN_ITERATIONS)..=i64::from(ARGON2_MAX_ITERATIONS))
+    )]
+    pub argon2_iterations: Option<u32>,
+
+    /// Argon2id 并行度 [默认: 4]
+    #[arg(
+        long,
+        value_parser = value_parser!(u32).range(i64::from(ARGON2_MIN_PARALLELISM)..=i64::from(ARGON2_MAX_PARALLELISM))
+    )]
+    pub argon2_parallelism: Option<u32>,
+
+    /// PBKDF2 迭代次数 [默认: 100000]
+    #[arg(
+        long,
+        conflicts_with_all = ["argon2_memory", "argon2_iterations", "argon2_parallelism"],
+        value_parser = value_parser!(u32).range(i64::from(PBKDF2_MIN_ITERATIONS)..=i64::from(PBKDF2_MAX_ITERATIONS))
+    )]
+    pub pbkdf2_iterations: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KdfKind {
+    Argon2id,
+    Pbkdf2,
+}
+
+impl KdfArgs {
+    /// 命令行指定的 KDF，没有指定任何参数时返回 None。
+    /// 未指定的参数沿用已有密钥文件中同一种 KDF 的参数，否则使用默认值
+    pub fn kdf(&self, stored: Option<Kdf>) -> Result<Option<Kdf>> {
+        let argon2 = self.argon2_memory.is_some()
+            || self.argon2_iterations.is_some()
+            || self.argon2_parallelism.is_some();
+        let kind = match (self.kdf, argon2, self.pbkdf2_iterations.is_some()) {
+            (None, false, false) => return Ok(None),
+            (Some(KdfKind::Argon2id), _, true) => {
+                bail!("--pbkdf2-iterations 只能与 --kdf pbkdf2 一起使用")
+            }
+            (Some(KdfKind::Pbkdf2), true, _) => {
+                bail!("--argon2-* 参数只能与 --kdf argon2id 一起使用")
+            }
+            (Some(kind), _, _) => kind,
+            (None, _, true) => KdfKind::Pbkdf2,
+            (None, _, false) => KdfKind::Argon2id,
+        };
+
+        Ok(Some(match (kind, stored) {
+            (
+                KdfKind::Argon2id,
+                Some(Kdf::Argon2id {
+                    memory_kib,
+                    iterations,
+                    parallelism,
+                }),
+            ) => Kdf::Argon2id {
+                memory_kib: self.argon2_memory.unwrap_or(memory_kib),
+                iterations: self.argon2_iterations.unwrap_or(iterations),
+                parallelism: self.argon2_parallelism.unwrap_or(parallelism),
+            },
+            (KdfKind::Argon2id, _) => Kdf::Argon2id {
+                memory_kib: self.argon2_memory.unwrap_or(ARGON2_MEMORY_KIB),
+                iterations: self.argon2_iterations.unwrap_or(ARGON2_ITERATIONS),
+                parallelism: self.argon2_parallelism.unwrap_or(ARGON2_PARALLELISM),
+            },
+            (KdfKind::Pbkdf2, Some(Kdf::Pbkdf2 { iterations })) => Kdf::Pbkdf2 {
+                iterations: self.pbkdf2_iterations.unwrap_or(iterations),
+            },
+            (KdfKind::Pbkdf2, _) => Kdf::Pbkdf2 {
+                iterations: self.pbkdf2_iterations.unwrap_or(PBKDF2_ITERS),
+            },
+        }))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// 加密目录（encrypt 的输出目录）
+    #[arg(short, long, value_parser = existing_dir)]
+    pub output: PathBuf,
+
+    /// 深度校验：完整解密并与原始文件哈希比对（需要密码）
+    #[arg(long)]
+    pub deep: bool,
+
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    /// 显示的日志条数
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub limit: usize,
+
+    /// 只显示指定状态的日志（如 failed, error）
+    #[arg(short, long)]
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct PruneArgs {
+    /// 删除早于指定天数的日志
+    #[arg(long, default_value_t = 30)]
+    pub older_than_days: u32,
+}
+
+/// 校验路径是否为已存在的目录
+fn existing_dir(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    if path.is_dir() {
+        Ok(path)
+    } else {
+        Err(format!("目录不存在: {}", value))
+    }
+}
@@ -0,0 +1,627 @@
+//! ZENC 容器格式：Zstd 压缩 + AES-256-GCM 加密
+//!
+//! VERSION 2（分段流式加密，内存占用与文件大小无关）：
+//!
+//! ```text
+//! [MAGIC: 4字节 "ZENC"]
+//! [VERSION: 1字节 = 2]
+//! [KEY_SCHEME: 1字节]  0 = 从密码派生，1 = 主密钥 + HKDF（见 keys 模块）
+//! [KDF: 变长]          仅 KEY_SCHEME = 0 时存在，格式同密钥文件中的 KDF 字段
+//! [SALT_LEN: 1字节]
+//! [SALT: 16字节]
+//! [CHECK: 32字节]      仅 KEY_SCHEME = 0 时存在，文件密钥派生出的校验值，用于识别密码错误
+//! [NONCE_PREFIX_LEN: 1字节]
+//! [NONCE_PREFIX: 7字节]
+//! [SEGMENT_SIZE: 4字节 LE]
+//! [SEGMENT 0: SEGMENT_SIZE + 16 字节]
+//! ...
+//! [最后一段: 不超过 SEGMENT_SIZE + 16 字节]
+//! ```
+//!
+//! 每段的 nonce 为 `NONCE_PREFIX || 段序号(u32 BE) || 最后一段标记(1字节)`（STREAM 构造），
+//! 因此段的重排、删除以及在段边界处的截断都会导致认证失败。
+//!
+//! 每段的附加认证数据（AAD）为完整的容器头字节加上文件在输出目录中的相对路径
+//! （与数据库相同的编码，见 pathenc 模块），文件头被修改或文件被移动/互换位置时解密都会失败。
+//! 密码已通过密钥文件或 CHECK 校验，因此认证失败总是报告为文件损坏而不是密码错误。
+//!
+//! 压缩前的数据流为 `[META_LEN: 4字节 LE][META][原始文件内容]`，
+//! META 为原始文件的权限、所有者、修改时间和扩展属性（格式见 metadata 模块）。
+//!
+//! VERSION 1（旧格式，只读）：整个压缩数据一次性加密，密钥固定使用 PBKDF2 100,000 次迭代从密码派生
+//!
+//! ```text
+//! [MAGIC: 4字节 "ZENC"]
+//! [VERSION: 1字节 = 1]
+//! [SALT_LEN: 1字节]
+//! [SALT: 16字节]
+//! [NONCE_LEN: 1字节]
+//! [NONCE: 12字节]
+//! [CIPHERTEXT: 变长]
+//! ```
+
+use crate::fsutil::AtomicFile;
+use crate::keys::{CHECK_LEN, Kdf, KeyRing, key_check_value};
+use crate::metadata::{FileMetadata, MAX_METADATA_LEN};
+use aes_gcm::{
+    Aes256Gcm, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use anyhow::Result;
+use rand::RngCore;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+use zstd::stream::{Decoder, Encoder};
+
+pub const MAGIC: &[u8; 4] = b"ZENC";
+pub const VERSION: u8 = 2;
+pub const SALT_LEN: usize = 16;
+pub const NONCE_LEN: usize = 12;
+pub const NONCE_PREFIX_LEN: usize = NONCE_LEN - 5;
+pub const SEGMENT_SIZE: usize = 1024 * 1024; // 每段明文 1MB
+pub const MAX_SEGMENT_SIZE: usize = 64 * 1024 * 1024; // 读取时允许的最大段长度
+pub const TAG_LEN: usize = 16;
+pub const ZSTD_WORKERS: u32 = 4; // Zstd 内部线程数
+
+/// 旧版本（整体加密）的容器版本号
+const VERSION_V1: u8 = 1;
+
+/// 每个文件的密钥从密码派生（PBKDF2）
+pub const KEY_SCHEME_PASSWORD: u8 = 0;
+/// 每个文件的密钥从主密钥派生（HKDF）
+pub const KEY_SCHEME_MASTER: u8 = 1;
+
+/// 解密失败的原因
+#[derive(Debug)]
+pub enum ContainerError {
+    /// 认证标签校验失败（通常是密码错误）
+    WrongPassword,
+    /// 文件头无效、数据被截断或无法解压
+    Corrupted(String),
+    /// 文件使用主密钥加密，但没有找到密钥文件
+    MissingKeyFile,
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerError::WrongPassword => write!(f, "密码错误"),
+            ContainerError::Corrupted(reason) => write!(f, "文件已损坏: {}", reason),
+            ContainerError::MissingKeyFile => write!(f, "缺少密钥文件"),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// 多线程压缩并加密文件（流式处理，内存占用固定）
+///
+/// `relative_path` 为输出文件在输出目录中的相对路径（以 `/` 分隔），与容器头一起作为 AAD
+pub fn compress_and_encrypt_mt(
+    input: &Path,
+    output: &Path,
+    keys: &KeyRing,
+    relative_path: &str,
+) -> Result<()> {
+    let metadata = FileMetadata::capture(input)?;
+    encrypt_stream(File::open(input)?, &metadata, output, keys, relative_path)
+}
+
+/// 压缩并加密内存中的数据（没有原始文件的元数据），格式与普通输出文件相同
+pub fn encrypt_bytes(
+    data: &[u8],
+    output: &Path,
+    keys: &KeyRing,
+    relative_path: &str,
+) -> Result<()> {
+    encrypt_stream(data, &FileMetadata::default(), output, keys, relative_path)
+}
+
+fn encrypt_stream(
+    mut input: impl Read,
+    metadata: &FileMetadata,
+    output: &Path,
+    keys: &KeyRing,
+    relative_path: &str,
+) -> Result<()> {
+    // 元数据过大时在创建输出文件前失败
+    let metadata = metadata.encode()?;
+
+    // 1. 生成随机 salt 和 nonce 前缀
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce_prefix);
+
+    // 2. 派生文件密钥：有主密钥时使用 HKDF，否则从密码派生
+    let (key_scheme, key) = match keys.master_key() {
+        Some(master) => (KEY_SCHEME_MASTER, master.derive_file_key(&salt)),
+        None => (
+            KEY_SCHEME_PASSWORD,
+            keys.kdf().derive(keys.password(), &salt)?,
+        ),
+    };
+    let cipher = Aes256Gcm::new(&key);
+
+    // 3. 写入容器头
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    header.push(key_scheme);
+    if key_scheme == KEY_SCHEME_PASSWORD {
+        header.extend_from_slice(&keys.kdf().encode());
+    }
+    header.push(SALT_LEN as u8);
+    header.extend_from_slice(&salt);
+    if key_scheme == KEY_SCHEME_PASSWORD {
+        header.extend_from_slice(&key_check_value(&key));
+    }
+    header.push(NONCE_PREFIX_LEN as u8);
+    header.extend_from_slice(&nonce_prefix);
+    header.extend_from_slice(&(SEGMENT_SIZE as u32).to_le_bytes());
+
+    // 写入临时文件，完成后再替换输出文件，中断时保留上一个完整版本
+    let mut output_file = BufWriter::new(AtomicFile::create(output)?);
+    output_file.write_all(&header)?;
+
+    // 4. Zstd 多线程压缩，压缩结果按段加密写出
+    let aad = associated_data(header, relative_path);
+    let segments = SegmentWriter::new(output_file, cipher, nonce_prefix, aad, SEGMENT_SIZE);
+    let mut encoder = Encoder::new(segments, 3)?;
+
+    // 启用 Zstd 多线程压缩（需要 zstdmt feature）
+    encoder.multithread(ZSTD_WORKERS)?;
+
+    // 数据流开头为原始文件的元数据
+    encoder.write_all(&(metadata.len() as u32).to_le_bytes())?;
+    encoder.write_all(&metadata)?;
+    io::copy(&mut input, &mut encoder)?;
+
+    let output_file = encoder.finish()?.finish()?;
+    output_file
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .commit()
+}
+
+/// 解密并解压文件（compress_and_encrypt_mt 的逆过程），并写回原始文件的元数据
+///
+/// `relative_path` 必须与加密时一致，否则认证失败。
+/// 失败时可通过 `downcast_ref::<ContainerError>()` 区分密码错误与文件损坏
+pub fn decrypt_and_decompress(
+    input: &Path,
+    output: &Path,
+    keys: &KeyRing,
+    relative_path: &str,
+) -> Result<()> {
+    // 失败时临时文件被删除，不保留解密了一半的文件，也不覆盖已有文件
+    let mut output_file = BufWriter::new(AtomicFile::create(output)?);
+    let metadata = decrypt_to_writer(input, &mut output_file, keys, relative_path)?;
+    output_file
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .commit()?;
+
+    match metadata {
+        Some(metadata) => metadata.apply(output),
+        None => Ok(()),
+    }
+}
+
+/// 解密并解压文件，将原始数据写入 `writer`（例如用于计算哈希而不落盘），
+/// 返回原始文件的元数据（VERSION 1 的旧文件没有元数据）
+pub fn decrypt_to_writer(
+    input: &Path,
+    writer: &mut impl Write,
+    keys: &KeyRing,
+    relative_path: &str,
+) -> Result<Option<FileMetadata>> {
+    let mut reader = HeaderReader::new(BufReader::new(File::open(input)?));
+
+    // 1. 解析容器头
+    let magic = read_header_bytes(&mut reader, MAGIC.len(), "文件过短")?;
+    if magic != MAGIC {
+        return Err(corrupted("文件头标识无效").into());
+    }
+    let version = read_header_bytes(&mut reader, 1, "缺少版本号")?[0];
+    let (key_scheme, kdf) = match version {
+        VERSION => {
+            let key_scheme = read_header_bytes(&mut reader, 1, "缺少密钥方案")?[0];
+            let kdf = if key_scheme == KEY_SCHEME_PASSWORD {
+                read_kdf(&mut reader)?
+            } else {
+                Kdf::LEGACY
+            };
+            (key_scheme, kdf)
+        }
+        VERSION_V1 => {
+            decrypt_v1(reader.inner, writer, keys.password())?;
+            return Ok(None);
+        }
+        _ => return Err(ContainerError::Corrupted(format!("不支持的版本: {}", version)).into()),
+    };
+
+    let salt_len = read_header_bytes(&mut reader, 1, "缺少 salt 长度")?[0];
+    let salt = read_header_bytes(&mut reader, salt_len as usize, "salt 被截断")?;
+    let check = if key_scheme == KEY_SCHEME_PASSWORD {
+        Some(read_header_bytes(&mut reader, CHECK_LEN, "校验值被截断")?)
+    } else {
+        None
+    };
+    let prefix_len = read_header_bytes(&mut reader, 1, "缺少 nonce 长度")?[0];
+    if prefix_len as usize != NONCE_PREFIX_LEN {
+        return Err(corrupted("nonce 长度无效").into());
+    }
+    let nonce_prefix = read_header_bytes(&mut reader, NONCE_PREFIX_LEN, "nonce 被截断")?;
+    let segment_size = read_header_bytes(&mut reader, 4, "缺少分段长度")?;
+    let segment_size = u32::from_le_bytes(segment_size.try_into().unwrap()) as usize;
+    if segment_size == 0 || segment_size > MAX_SEGMENT_SIZE {
+        return Err(corrupted("分段长度无效").into());
+    }
+
+    // 2. 按密钥方案派生文件密钥
+    let key = match key_scheme {
+        KEY_SCHEME_PASSWORD => {
+            let key = kdf.derive(keys.password(), &salt)?;
+            if check.is_some_and(|check| check != key_check_value(&key)) {
+                return Err(ContainerError::WrongPassword.into());
+            }
+            key
+        }
+        KEY_SCHEME_MASTER => keys
+            .master_key()
+            .ok_or(ContainerError::MissingKeyFile)?
+            .derive_file_key(&salt),
+        _ => {
+            return Err(
+                ContainerError::Corrupted(format!("未知的密钥方案: {}", key_scheme)).into(),
+            );
+        }
+    };
+    let cipher = Aes256Gcm::new(&key);
+
+    // 3. 逐段解密并流式解压
+    let aad = associated_data(reader.bytes, relative_path);
+    let segments = SegmentReader::new(
+        reader.inner,
+        cipher,
+        nonce_prefix.try_into().unwrap(),
+        aad,
+        segment_size,
+    );
+    let mut decoder = Decoder::new(segments)?;
+
+    // 4. 读取数据流开头的元数据
+    let mut len = [0u8; 4];
+    decoder.read_exact(&mut len).map_err(stream_error)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_METADATA_LEN {
+        return Err(corrupted("元数据长度无效").into());
+    }
+    let mut data = vec![0u8; len];
+    decoder.read_exact(&mut data).map_err(stream_error)?;
+    let metadata = FileMetadata::decode(&data).map_err(ContainerError::Corrupted)?;
+
+    io::copy(&mut decoder, writer).map_err(stream_error)?;
+
+    // Zstd 帧结束后必须恰好到达最后一段
+    if !decoder.finish().get_ref().finished {
+        return Err(corrupted("压缩数据之后存在多余的分段").into());
+    }
+
+    Ok(Some(metadata))
+}
+
+/// 解密错误通过 io::Error 传递出来，取回原始的错误类型
+fn stream_error(e: io::Error) -> ContainerError {
+    match e.get_ref().and_then(|e| e.downcast_ref::<ContainerError>()) {
+        // 密码已通过密钥文件或容器头中的校验值确认，认证失败只可能是文件头、路径或数据不匹配
+        Some(ContainerError::WrongPassword) => corrupted("认证失败（文件头、路径或数据不匹配）"),
+        Some(ContainerError::Corrupted(reason)) => ContainerError::Corrupted(reason.clone()),
+        Some(ContainerError::MissingKeyFile) => ContainerError::MissingKeyFile,
+        None if e.kind() == io::ErrorKind::UnexpectedEof => corrupted("数据被截断"),
+        None => ContainerError::Corrupted(format!("解压失败: {}", e)),
+    }
+}
+
+/// 解密 VERSION 1 的旧格式（整体加密）
+fn decrypt_v1(mut reader: impl Read, writer: &mut impl Write, password: &str) -> Result<()> {
+    let salt_len = read_header_bytes(&mut reader, 1, "缺少 salt 长度")?[0];
+    let salt = read_header_bytes(&mut reader, salt_len as usize, "salt 被截断")?;
+    let nonce_len = read_header_bytes(&mut reader, 1, "缺少 nonce 长度")?[0];
+    if nonce_len as usize != NONCE_LEN {
+        return Err(corrupted("nonce 长度无效").into());
+    }
+    let nonce_bytes = read_header_bytes(&mut reader, NONCE_LEN, "nonce 被截断")?;
+    let mut ciphertext = Vec::new();
+    reader.read_to_end(&mut ciphertext)?;
+
+    // 从密码派生密钥并解密（认证失败视为密码错误）
+    let cipher = Aes256Gcm::new(&Kdf::LEGACY.derive(password, &salt)?);
+    let compressed = cipher
+        .decrypt(Nonce::from_slice(&nonce_bytes), ciphertext.as_ref())
+        .map_err(|_| ContainerError::WrongPassword)?;
+
+    // Zstd 解压并写出
+    let original_data = zstd::stream::decode_all(compressed.as_slice())
+        .map_err(|e| ContainerError::Corrupted(format!("解压失败: {}", e)))?;
+    writer.write_all(&original_data)?;
+
+    Ok(())
+}
+
+fn corrupted(reason: &str) -> ContainerError {
+    ContainerError::Corrupted(reason.to_string())
+}
+
+/// 附加认证数据：容器头字节 || 相对路径
+fn associated_data(mut header: Vec<u8>, relative_path: &str) -> Vec<u8> {
+    header.extend_from_slice(relative_path.as_bytes());
+    header
+}
+
+/// 读取容器头的同时记录读到的字节，用于构造 AAD
+struct HeaderReader<R: Read> {
+    inner: R,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> HeaderReader<R> {
+    fn new(inner: R) -> Self {
+        HeaderReader {
+            inner,
+            bytes: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for HeaderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// 读取容器头中的 KDF 字段
+fn read_kdf(reader: &mut impl Read) -> Result<Kdf> {
+    let id = read_header_bytes(reader, 1, "缺少 KDF")?[0];
+    let params_len = read_header_bytes(reader, 1, "缺少 KDF 参数长度")?[0];
+    let params = read_header_bytes(reader, params_len as usize, "KDF 参数被截断")?;
+    Ok(Kdf::decode(id, &params).map_err(ContainerError::Corrupted)?)
+}
+
+/// 读取定长的容器头字段，数据不足视为文件损坏
+fn read_header_bytes(reader: &mut impl Read, len: usize, reason: &str) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => anyhow::Error::from(corrupted(reason)),
+        _ => e.into(),
+    })?;
+    Ok(buf)
+}
+
+/// 计算第 `index` 段的 nonce：前缀 || 段序号(BE) || 最后一段标记
+fn segment_nonce(prefix: &[u8; NONCE_PREFIX_LEN], index: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    nonce
+}
+
+/// 分段加密写入器：缓存一段明文，写满后加密输出
+///
+/// 总是保留最后一段直到 `finish`，以便为其设置最后一段标记
+struct SegmentWriter<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    aad: Vec<u8>,
+    segment_size: usize,
+    index: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> SegmentWriter<W> {
+    fn new(
+        inner: W,
+        cipher: Aes256Gcm,
+        nonce_prefix: [u8; NONCE_PREFIX_LEN],
+        aad: Vec<u8>,
+        segment_size: usize,
+    ) -> Self {
+        SegmentWriter {
+            inner,
+            cipher,
+            nonce_prefix,
+            aad,
+            segment_size,
+            index: 0,
+            buffer: Vec::with_capacity(segment_size),
+        }
+    }
+
+    /// 加密并写出缓存中的一段
+    fn write_segment(&mut self, last: bool) -> io::Result<()> {
+        let nonce = segment_nonce(&self.nonce_prefix, self.index, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.buffer,
+                    aad: &self.aad,
+                },
+            )
+            .map_err(|e| io::Error::other(format!("加密失败: {:?}", e)))?;
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.clear();
+        self.index = self
+            .index
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("文件过大：分段数量超出上限"))?;
+        Ok(())
+    }
+
+    /// 写出最后一段并返回内部写入器
+    fn finish(mut self) -> io::Result<W> {
+        self.write_segment(true)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SegmentWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        // 缓存已满且还有后续数据，说明缓存中的段不是最后一段
+        if self.buffer.len() == self.segment_size {
+            self.write_segment(false)?;
+        }
+        let len = data.len().min(self.segment_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// 分段解密读取器
+struct SegmentReader<R: Read> {
+    inner: R,
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    aad: Vec<u8>,
+    segment_size: usize,
+    index: u32,
+    /// 预读的下一段的第一个字节
+    peeked: Option<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> SegmentReader<R> {
+    fn new(
+        inner: R,
+        cipher: Aes256Gcm,
+        nonce_prefix: [u8; NONCE_PREFIX_LEN],
+        aad: Vec<u8>,
+        segment_size: usize,
+    ) -> Self {
+        SegmentReader {
+            inner,
+            cipher,
+            nonce_prefix,
+            aad,
+            segment_size,
+            index: 0,
+            peeked: None,
+            plaintext: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// 读取尽可能多的字节直到填满缓冲区或到达文件末尾
+    fn read_full(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.inner.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    /// 读取并解密下一段
+    fn next_segment(&mut self) -> io::Result<()> {
+        let mut ciphertext = vec![0u8; self.segment_size + TAG_LEN];
+        let mut filled = 0;
+        if let Some(byte) = self.peeked.take() {
+            ciphertext[0] = byte;
+            filled = 1;
+        }
+        filled += self.read_full(&mut ciphertext[filled..])?;
+        ciphertext.truncate(filled);
+
+        // 段不满或其后没有数据时为最后一段
+        let last = if filled < self.segment_size + TAG_LEN {
+            true
+        } else {
+            let mut next = [0u8; 1];
+            if self.read_full(&mut next)? == 0 {
+                true
+            } else {
+                self.peeked = Some(next[0]);
+                false
+            }
+        };
+
+        if ciphertext.len() < TAG_LEN {
+            return Err(io::Error::other(corrupted("数据被截断")));
+        }
+
+        self.plaintext = match self.decrypt_segment(&ciphertext, last) {
+            Ok(plaintext) => plaintext,
+            Err(_) => return Err(io::Error::other(self.segment_error(&ciphertext, last))),
+        };
+        self.position = 0;
+        self.finished = last;
+        self.index = self
+            .index
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other(corrupted("分段数量超出上限")))?;
+        Ok(())
+    }
+
+    /// 解密当前序号的一段
+    fn decrypt_segment(&self, ciphertext: &[u8], last: bool) -> aes_gcm::aead::Result<Vec<u8>> {
+        let nonce = segment_nonce(&self.nonce_prefix, self.index, last);
+        self.cipher.decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &self.aad,
+            },
+        )
+    }
+
+    /// 判断认证失败的原因
+    fn segment_error(&self, ciphertext: &[u8], last: bool) -> ContainerError {
+        // 作为非最后一段可以解密，说明文件在段边界处被截断
+        if last && self.decrypt_segment(ciphertext, false).is_ok() {
+            return corrupted("文件被截断");
+        }
+        // 之前的段已成功解密，说明密码正确、数据被篡改
+        if self.index > 0 {
+            return ContainerError::Corrupted(format!("第 {} 段认证失败", self.index));
+        }
+        ContainerError::WrongPassword
+    }
+}
+
+impl<R: Read> Read for SegmentReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.next_segment()?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
@@ -1,10 +1,12 @@
+use crate::migrations;
+use crate::pathenc::encode_path;
 use anyhow::{Context, Result};
-use rusqlite::{Connection, params};
-use std::path::PathBuf;
+use rusqlite::{Connection, OpenFlags, ToSql, params};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
 /// 文件记录
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileRecord {
     pub id: Option<i64>,
     pub relative_path: String,
@@ -14,6 +16,18 @@ pub struct FileRecord {
     pub original_size: u64,
     pub output_size: u64,
     pub created_at: String,
+    /// 加密文件名模式下输出文件的对象 ID（输出文件位于 `objects/` 下）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_id: Option<String>,
+}
+
+/// 来源记录：一对输入目录和输出目录（与相对路径相同的编码），文件记录按来源隔离
+#[derive(Debug, Clone)]
+pub struct SourceRecord {
+    pub id: i64,
+    pub input_root: String,
+    pub output_root: String,
+    pub created_at: String,
 }
 
 /// 日志记录
@@ -26,23 +40,65 @@ pub struct LogRecord {
     pub timestamp: String,
 }
 
+/// 目录项类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// 空目录
+    Dir,
+    /// 符号链接
+    Symlink,
+}
+
+impl EntryKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "dir" => Some(EntryKind::Dir),
+            "symlink" => Some(EntryKind::Symlink),
+            _ => None,
+        }
+    }
+}
+
+/// 目录项记录：没有输出文件、还原时需要单独创建的空目录和符号链接
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryRecord {
+    pub relative_path: String,
+    pub kind: EntryKind,
+    /// 符号链接指向的路径
+    pub target: Option<String>,
+}
+
+/// 保存在输出目录中的目录数据库文件名
+pub const CATALOG_FILE_NAME: &str = "catalog.db";
+
 /// 数据库管理器
 pub struct Database {
     pub conn: Connection,
 }
 
 impl Database {
-    /// 创建或打开数据库
+    /// 创建或打开默认位置的数据库
     pub fn new() -> Result<Self> {
-        let db_path = Self::get_db_path()?;
+        Self::open(&Self::get_db_path()?)
+    }
 
+    /// 创建或打开指定路径的数据库
+    pub fn open(db_path: &Path) -> Result<Self> {
         // 确保目录存在
         if let Some(parent) = db_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
         let conn =
-            Connection::open(&db_path).context(format!("无法打开数据库: {}", db_path.display()))?;
+            Connection::open(db_path).context(format!("无法打开数据库: {}", db_path.display()))?;
 
         let db = Database { conn };
         db.init_tables()?;
@@ -50,76 +106,157 @@ impl Database {
         Ok(db)
     }
 
-    /// 获取数据库路径
-    fn get_db_path() -> Result<PathBuf> {
+    /// 以只读方式打开指定路径的数据库（用于预演）：不创建文件，也不执行迁移。
+    /// 数据库不存在或结构版本较旧时返回空的内存数据库
+    pub fn open_read_only(db_path: &Path) -> Result<Self> {
+        if db_path.is_file() {
+            let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .context(format!("无法打开数据库: {}", db_path.display()))?;
+            if migrations::check_version(&conn)? == migrations::SCHEMA_VERSION {
+                return Ok(Database { conn });
+            }
+        }
+
+        let db = Database {
+            conn: Connection::open_in_memory()?,
+        };
+        db.init_tables()?;
+        Ok(db)
+    }
+
+    /// 当前打开的数据库文件路径（内存数据库为 None）
+    pub fn path(&self) -> Option<PathBuf> {
+        self.conn
+            .path()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// 获取默认数据库路径
+    pub fn get_db_path() -> Result<PathBuf> {
         let home_dir = dirs::home_dir().context("无法获取用户主目录")?;
         Ok(home_dir.join(".xor").join("data.db"))
     }
 
-    /// 初始化数据库表
+    /// 初始化数据库表（执行尚未执行的结构迁移）
     pub fn init_tables(&self) -> Result<()> {
-        // 创建文件表
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS files (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                relative_path TEXT NOT NULL UNIQUE,
-                modified_time TEXT NOT NULL,
-                original_hash TEXT NOT NULL,
-                output_hash TEXT NOT NULL,
-                original_size INTEGER NOT NULL DEFAULT 0,
-                output_size INTEGER NOT NULL DEFAULT 0,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL
-            )",
-            [],
-        )?;
+        migrations::migrate(&self.conn)
+    }
 
-        // 添加文件大小列（如果表已存在但没有这些列）
-        let _ = self.conn.execute(
-            "ALTER TABLE files ADD COLUMN original_size INTEGER NOT NULL DEFAULT 0",
-            [],
-        );
-        let _ = self.conn.execute(
-            "ALTER TABLE files ADD COLUMN output_size INTEGER NOT NULL DEFAULT 0",
-            [],
-        );
+    /// 获取或创建来源（输入目录和输出目录应为规范化后的绝对路径），返回来源 ID
+    pub fn register_source(&self, input_root: &Path, output_root: &Path) -> Result<i64> {
+        let input_root = encode_path(input_root);
+        let output_root = encode_path(output_root);
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
 
-        // 创建日志表
         self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS logs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                file_path TEXT NOT NULL,
-                action TEXT NOT NULL,
-                status TEXT NOT NULL,
-                message TEXT,
-                timestamp TEXT NOT NULL
-            )",
-            [],
+            "INSERT OR IGNORE INTO sources (input_root, output_root, created_at)
+             VALUES (?1, ?2, ?3)",
+            params![input_root, output_root, now],
         )?;
 
-        // 创建索引
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_files_path ON files(relative_path)",
-            [],
+        let id = self.conn.query_row(
+            "SELECT id FROM sources WHERE input_root = ?1 AND output_root = ?2",
+            params![input_root, output_root],
+            |row| row.get(0),
         )?;
+        Ok(id)
+    }
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_logs_timestamp ON logs(timestamp)",
+    /// 获取所有来源
+    pub fn get_sources(&self) -> Result<Vec<SourceRecord>> {
+        self.query_sources("1", &[])
+    }
+
+    /// 查找已登记的来源（不创建）
+    pub fn find_source(
+        &self,
+        input_root: &Path,
+        output_root: &Path,
+    ) -> Result<Option<SourceRecord>> {
+        let sources = self.query_sources(
+            "input_root = ?1 AND output_root = ?2",
+            &[&encode_path(input_root), &encode_path(output_root)],
+        )?;
+        Ok(sources.into_iter().next())
+    }
+
+    /// 获取输出到指定目录的所有来源
+    pub fn find_sources_by_output(&self, output_root: &Path) -> Result<Vec<SourceRecord>> {
+        self.query_sources("output_root = ?1", &[&encode_path(output_root)])
+    }
+
+    /// 按条件查询来源
+    fn query_sources(&self, condition: &str, params: &[&dyn ToSql]) -> Result<Vec<SourceRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, input_root, output_root, created_at
+             FROM sources WHERE {} ORDER BY id",
+            condition
+        ))?;
+
+        let rows = stmt.query_map(params, |row| {
+            Ok(SourceRecord {
+                id: row.get(0)?,
+                input_root: row.get(1)?,
+                output_root: row.get(2)?,
+                created_at: row.get(3)?,
+            })
+        })?;
+
+        let mut sources = Vec::new();
+        for source in rows {
+            sources.push(source?);
+        }
+
+        Ok(sources)
+    }
+
+    /// 统计没有关联来源的旧记录数量
+    pub fn count_unsourced_files(&self) -> Result<usize> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE source_id IS NULL",
             [],
+            |row| row.get(0),
         )?;
+        Ok(count as usize)
+    }
 
-        Ok(())
+    /// 获取没有关联来源的旧记录（升级前生成，不含已标记删除的记录）
+    pub fn get_unsourced_files(&self) -> Result<Vec<FileRecord>> {
+        self.query_files(None, "deleted_at IS NULL")
     }
 
-    /// 检查文件是否存在于数据库中
-    pub fn file_exists(&self, relative_path: &str) -> Result<Option<FileRecord>> {
+    /// 将没有关联来源的旧记录归入指定来源（使用事务），返回归入的条数
+    pub fn adopt_files(&mut self, source_id: i64, records: &[FileRecord]) -> Result<usize> {
+        if records.is_empty() {
+            return Ok(0);
+        }
+
+        let tx = self.conn.transaction()?;
+        let mut adopted = 0;
+
+        {
+            let mut stmt =
+                tx.prepare("UPDATE files SET source_id = ?1 WHERE id = ?2 AND source_id IS NULL")?;
+
+            for record in records {
+                adopted += stmt.execute(params![source_id, record.id])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(adopted)
+    }
+
+    /// 检查文件是否存在于数据库中（已标记删除的记录视为不存在）
+    pub fn file_exists(&self, source_id: i64, relative_path: &str) -> Result<Option<FileRecord>> {
         let mut stmt = self.conn.prepare(
             "SELECT id, relative_path, modified_time, original_hash, output_hash, 
-                    COALESCE(original_size, 0), COALESCE(output_size, 0), created_at
-             FROM files WHERE relative_path = ?1",
+                    COALESCE(original_size, 0), COALESCE(output_size, 0), created_at, object_id
+             FROM files WHERE source_id = ?1 AND relative_path = ?2 AND deleted_at IS NULL",
         )?;
 
-        let mut rows = stmt.query(params![relative_path])?;
+        let mut rows = stmt.query(params![source_id, relative_path])?;
 
         if let Some(row) = rows.next()? {
             Ok(Some(FileRecord {
@@ -131,6 +268,7 @@ impl Database {
                 original_size: row.get(5)?,
                 output_size: row.get(6)?,
                 created_at: row.get(7)?,
+                object_id: row.get(8)?,
             }))
         } else {
             Ok(None)
@@ -138,21 +276,23 @@ impl Database {
     }
 
     /// 插入或更新文件记录
-    #[allow(dead_code)]
-    pub fn upsert_file(&self, record: &FileRecord) -> Result<()> {
+    pub fn upsert_file(&self, source_id: i64, record: &FileRecord) -> Result<()> {
         let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
 
         self.conn.execute(
-            "INSERT INTO files (relative_path, modified_time, original_hash, output_hash, original_size, output_size, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
-             ON CONFLICT(relative_path) DO UPDATE SET
+            "INSERT INTO files (source_id, relative_path, modified_time, original_hash, output_hash, original_size, output_size, created_at, updated_at, object_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT(source_id, relative_path) DO UPDATE SET
                 modified_time = excluded.modified_time,
                 original_hash = excluded.original_hash,
                 output_hash = excluded.output_hash,
                 original_size = excluded.original_size,
                 output_size = excluded.output_size,
-                updated_at = excluded.updated_at",
+                updated_at = excluded.updated_at,
+                object_id = excluded.object_id,
+                deleted_at = NULL",
             params![
+                source_id,
                 &record.relative_path,
                 &record.modified_time,
                 &record.original_hash,
@@ -161,6 +301,7 @@ impl Database {
                 &record.output_size,
                 &now,
                 &now,
+                &record.object_id,
             ],
         )?;
 
@@ -168,7 +309,7 @@ impl Database {
     }
 
     /// 批量插入或更新文件记录（使用事务）
-    pub fn batch_upsert_files(&mut self, records: &[FileRecord]) -> Result<()> {
+    pub fn batch_upsert_files(&mut self, source_id: i64, records: &[FileRecord]) -> Result<()> {
         if records.is_empty() {
             return Ok(());
         }
@@ -178,19 +319,22 @@ impl Database {
 
         {
             let mut stmt = tx.prepare(
-                "INSERT INTO files (relative_path, modified_time, original_hash, output_hash, original_size, output_size, created_at, updated_at)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
-                 ON CONFLICT(relative_path) DO UPDATE SET
+                "INSERT INTO files (source_id, relative_path, modified_time, original_hash, output_hash, original_size, output_size, created_at, updated_at, object_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT(source_id, relative_path) DO UPDATE SET
                     modified_time = excluded.modified_time,
                     original_hash = excluded.original_hash,
                     output_hash = excluded.output_hash,
                     original_size = excluded.original_size,
                     output_size = excluded.output_size,
-                    updated_at = excluded.updated_at"
+                    updated_at = excluded.updated_at,
+                    object_id = excluded.object_id,
+                    deleted_at = NULL"
             )?;
 
             for record in records {
                 stmt.execute(params![
+                    source_id,
                     &record.relative_path,
                     &record.modified_time,
                     &record.original_hash,
@@ -199,6 +343,7 @@ impl Database {
                     &record.output_size,
                     &now,
                     &now,
+                    &record.object_id,
                 ])?;
             }
         }
@@ -253,16 +398,123 @@ impl Database {
         Ok(())
     }
 
-    /// 获取所有文件记录
-    #[allow(dead_code)]
-    pub fn get_all_files(&self) -> Result<Vec<FileRecord>> {
+    /// 获取所有文件记录（不含已标记删除的记录）
+    pub fn get_all_files(&self, source_id: i64) -> Result<Vec<FileRecord>> {
+        self.query_files(Some(source_id), "deleted_at IS NULL")
+    }
+
+    /// 获取已标记删除的文件记录
+    pub fn get_deleted_files(&self, source_id: i64) -> Result<Vec<FileRecord>> {
+        self.query_files(Some(source_id), "deleted_at IS NOT NULL")
+    }
+
+    /// 将文件记录标记为已删除（使用事务），返回标记的条数
+    pub fn mark_deleted(&mut self, source_id: i64, relative_paths: &[String]) -> Result<usize> {
+        if relative_paths.is_empty() {
+            return Ok(0);
+        }
+
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let tx = self.conn.transaction()?;
+        let mut marked = 0;
+
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE files SET deleted_at = ?3, updated_at = ?3
+                 WHERE source_id = ?1 AND relative_path = ?2 AND deleted_at IS NULL",
+            )?;
+
+            for relative_path in relative_paths {
+                marked += stmt.execute(params![source_id, relative_path, &now])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(marked)
+    }
+
+    /// 获取来源下的所有目录项
+    pub fn get_entries(&self, source_id: i64) -> Result<Vec<EntryRecord>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, relative_path, modified_time, original_hash, output_hash, 
-                    COALESCE(original_size, 0), COALESCE(output_size, 0), created_at
-             FROM files ORDER BY relative_path",
+            "SELECT relative_path, kind, target FROM entries
+             WHERE source_id = ?1 ORDER BY relative_path",
         )?;
 
-        let rows = stmt.query_map([], |row| {
+        let rows = stmt.query_map(params![source_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (relative_path, kind, target) = row?;
+            let kind = EntryKind::parse(&kind)
+                .with_context(|| format!("未知的目录项类型: {} ({})", kind, relative_path))?;
+            entries.push(EntryRecord {
+                relative_path,
+                kind,
+                target,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// 更新来源下的目录项（使用事务），`remove_missing` 为 true 时删除不在 `entries` 中的旧目录项
+    pub fn sync_entries(
+        &mut self,
+        source_id: i64,
+        entries: &[EntryRecord],
+        remove_missing: bool,
+    ) -> Result<()> {
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let tx = self.conn.transaction()?;
+
+        if remove_missing {
+            tx.execute(
+                "DELETE FROM entries WHERE source_id = ?1",
+                params![source_id],
+            )?;
+        }
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO entries (source_id, relative_path, kind, target, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(source_id, relative_path) DO UPDATE SET
+                    kind = excluded.kind,
+                    target = excluded.target,
+                    updated_at = excluded.updated_at",
+            )?;
+
+            for entry in entries {
+                stmt.execute(params![
+                    source_id,
+                    &entry.relative_path,
+                    entry.kind.as_str(),
+                    &entry.target,
+                    &now,
+                ])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 按条件查询来源下的文件记录
+    /// 查询来源下的文件记录，`source_id` 为 None 时查询没有关联来源的旧记录
+    fn query_files(&self, source_id: Option<i64>, condition: &str) -> Result<Vec<FileRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, relative_path, modified_time, original_hash, output_hash, 
+                    COALESCE(original_size, 0), COALESCE(output_size, 0), created_at, object_id
+             FROM files WHERE source_id IS ?1 AND {} ORDER BY relative_path",
+            condition
+        ))?;
+
+        let rows = stmt.query_map(params![source_id], |row| {
             Ok(FileRecord {
                 id: Some(row.get(0)?),
                 relative_path: row.get(1)?,
@@ -272,6 +524,7 @@ impl Database {
                 original_size: row.get(5)?,
                 output_size: row.get(6)?,
                 created_at: row.get(7)?,
+                object_id: row.get(8)?,
             })
         })?;
 
@@ -284,14 +537,23 @@ impl Database {
     }
 
     /// 获取最近的日志
-    #[allow(dead_code)]
     pub fn get_recent_logs(&self, limit: usize) -> Result<Vec<LogRecord>> {
+        self.get_recent_logs_by_status(limit, None)
+    }
+
+    /// 获取最近的日志，可按状态过滤
+    pub fn get_recent_logs_by_status(
+        &self,
+        limit: usize,
+        status: Option<&str>,
+    ) -> Result<Vec<LogRecord>> {
         let mut stmt = self.conn.prepare(
             "SELECT file_path, action, status, message, timestamp 
-             FROM logs ORDER BY timestamp DESC LIMIT ?1",
+             FROM logs WHERE ?2 IS NULL OR status = ?2
+             ORDER BY timestamp DESC LIMIT ?1",
         )?;
 
-        let rows = stmt.query_map(params![limit], |row| {
+        let rows = stmt.query_map(params![limit, status], |row| {
             Ok(LogRecord {
                 file_path: row.get(0)?,
                 action: row.get(1)?,
@@ -309,6 +571,14 @@ impl Database {
         Ok(logs)
     }
 
+    /// 删除早于指定时间的日志，返回删除条数
+    pub fn prune_logs(&self, before: &str) -> Result<usize> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM logs WHERE timestamp < ?1", params![before])?;
+        Ok(deleted)
+    }
+
     /// 获取数据库路径（用于显示）
     pub fn get_db_path_string() -> Result<String> {
         Ok(Self::get_db_path()?.display().to_string())
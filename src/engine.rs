@@ -0,0 +1,1906 @@
+//! 目录级别的加密、还原与校验流程
+
+use crate::container::{
+    ContainerError, compress_and_encrypt_mt, decrypt_and_decompress, decrypt_to_writer,
+};
+use crate::db::{
+    CATALOG_FILE_NAME, Database, EntryKind, EntryRecord, FileRecord, LogRecord, SourceRecord,
+};
+use crate::fsutil::{compute_file_hash_simd, get_modified_time, same_modified_time};
+use crate::keys::{KEY_FILE_NAME, Kdf, KeyRing, MasterKey, check_kdf};
+use crate::manifest::{Change, ManifestFormat, write_delta_manifest, write_manifest};
+use crate::names::{
+    INDEX_FILE_NAME, NameIndex, OBJECTS_DIR, load_index, new_object_id, object_relative_path,
+    uses_encrypted_names, write_index,
+};
+use crate::pathenc::{decode_path, decode_relative_path, encode_path};
+use crate::walk::{FileFilter, SkippedEntry, SymlinkPolicy, WalkResult, walk_input};
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs, mem,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use walkdir::WalkDir;
+
+/// 加密输出文件的扩展名
+pub const OUTPUT_SUFFIX: &str = "zstd.enc";
+/// 输出目录中存放归档孤立输出文件的目录
+pub const ARCHIVE_DIR: &str = ".archive";
+/// 输出目录中的清单文件名（不含扩展名）
+pub const MANIFEST_STEM: &str = "manifest";
+/// 输出目录中本次运行的变更清单文件名（不含扩展名）
+pub const DELTA_MANIFEST_STEM: &str = "manifest-delta";
+
+/// 清单文件名，如 `manifest.json`
+pub fn manifest_file_name(stem: &str, format: ManifestFormat) -> String {
+    format!("{}.{}", stem, format.extension())
+}
+
+/// 加密选项
+#[derive(Debug, Clone)]
+pub struct EncryptOptions {
+    /// 输入目录
+    pub input: PathBuf,
+    /// 输出目录
+    pub output: PathBuf,
+    /// 加密密码
+    pub password: String,
+    /// 新建密钥文件时使用的 KDF（None 时使用默认参数）。
+    /// 已有密钥文件时沿用其中的参数，指定的参数与之不一致时返回错误
+    pub kdf: Option<Kdf>,
+    /// 源文件被删除后如何处理其输出文件
+    pub orphans: OrphanPolicy,
+    /// 处理过程中分批写入数据库的时机
+    pub flush: FlushPolicy,
+    /// 设置为 true 后不再开始处理新文件，已排队的记录写入数据库后返回
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// 忽略规则和大小/时间过滤条件
+    pub filter: FileFilter,
+    /// 符号链接的处理方式
+    pub symlinks: SymlinkPolicy,
+    /// 使用加密文件名（输出目录中已有加密索引时总是启用）
+    pub encrypt_names: bool,
+    /// 生成的清单格式
+    pub manifest_formats: Vec<ManifestFormat>,
+    /// 是否额外生成本次运行的变更清单
+    pub delta_manifest: bool,
+}
+
+impl Default for EncryptOptions {
+    /// 与命令行的默认值一致：保留孤立输出文件、跳过符号链接、生成 CSV 清单。
+    /// 输入/输出目录和密码需要调用方设置
+    fn default() -> Self {
+        EncryptOptions {
+            input: PathBuf::new(),
+            output: PathBuf::new(),
+            password: String::new(),
+            kdf: None,
+            orphans: OrphanPolicy::default(),
+            flush: FlushPolicy::default(),
+            cancel: None,
+            filter: FileFilter::default(),
+            symlinks: SymlinkPolicy::default(),
+            encrypt_names: false,
+            manifest_formats: vec![ManifestFormat::Csv],
+            delta_manifest: false,
+        }
+    }
+}
+
+/// 分批写入数据库的时机：队列中的条目（文件记录和日志）达到 `batch_size`，
+/// 或距上次写入超过 `interval` 时写入一次。进程被中断时最多丢失一批记录，
+/// 下次运行只需重新处理这些文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushPolicy {
+    pub batch_size: usize,
+    pub interval: Duration,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy {
+            batch_size: 100,
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// 源文件被删除后如何处理遗留的输出文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrphanPolicy {
+    /// 保留输出文件
+    #[default]
+    Keep,
+    /// 删除输出文件
+    Remove,
+    /// 移动到输出目录下的 `.archive/<时间>/`，保持原相对路径
+    Archive,
+}
+
+/// 文件处理状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// 首次处理的文件
+    New,
+    /// 重新处理的已存在文件
+    Updated,
+}
+
+/// 处理成功的文件
+#[derive(Debug, Clone)]
+pub struct ProcessedFile {
+    pub record: FileRecord,
+    pub status: FileStatus,
+}
+
+/// 处理失败的文件
+#[derive(Debug, Clone)]
+pub struct FailedFile {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// 源文件已被删除的记录
+#[derive(Debug, Clone)]
+pub struct DeletedFile {
+    pub relative_path: String,
+    /// 按 OrphanPolicy 处理后的输出文件位置（删除或不存在时为 None）
+    pub output: Option<PathBuf>,
+}
+
+/// 处理过程中的单个文件事件（用于实时显示进度）
+#[derive(Debug, Clone)]
+pub enum FileEvent {
+    Processed(ProcessedFile),
+    Failed(FailedFile),
+    Deleted(DeletedFile),
+}
+
+/// 加密结果
+#[derive(Debug, Clone)]
+pub struct EncryptReport {
+    /// 输入目录中找到的文件数
+    pub total_files: usize,
+    /// 本次处理（新增或更新）的文件
+    pub processed: Vec<ProcessedFile>,
+    /// 处理失败的文件
+    pub failed: Vec<FailedFile>,
+    /// 本次标记为已删除的文件
+    pub deleted: Vec<DeletedFile>,
+    /// 记录到数据库的空目录和符号链接
+    pub entries: Vec<EntryRecord>,
+    /// 跳过的特殊文件和符号链接（已写入日志）
+    pub skipped: Vec<SkippedEntry>,
+    /// 输入目录是否完整遍历（有目录无法读取时跳过删除检测）
+    pub walk_complete: bool,
+    /// 是否被取消（未处理完所有文件，跳过删除检测）
+    pub interrupted: bool,
+    /// 写入数据库的日志条数
+    pub logs_written: usize,
+    /// 清单路径（包含该来源的所有文件），每种格式一个
+    pub manifest_paths: Vec<PathBuf>,
+    /// 变更清单路径（未要求生成时为空）
+    pub delta_manifest_paths: Vec<PathBuf>,
+    /// 是否使用加密文件名（此时不生成会泄露文件名的清单）
+    pub encrypt_names: bool,
+    /// 运行摘要
+    pub summary: RunSummary,
+    /// 密钥文件使用的 KDF
+    pub kdf: Kdf,
+}
+
+/// 增量处理对单个文件的判断结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedAction {
+    /// 数据库中没有记录
+    New,
+    /// 修改时间和哈希均变化
+    Changed,
+    /// 记录存在但输出文件缺失
+    MissingOutput,
+    /// 仅修改时间变化，内容未变
+    Touched,
+    /// 修改时间未变化
+    Unchanged,
+    /// 不在大小/时间过滤范围内
+    Filtered,
+    /// 源文件已删除
+    Delete,
+}
+
+impl PlannedAction {
+    /// 是否需要压缩加密
+    pub fn needs_processing(&self) -> bool {
+        matches!(
+            self,
+            PlannedAction::New | PlannedAction::Changed | PlannedAction::MissingOutput
+        )
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PlannedAction::New => "新增",
+            PlannedAction::Changed => "更新",
+            PlannedAction::MissingOutput => "重新生成",
+            PlannedAction::Touched => "跳过 (仅修改时间变化)",
+            PlannedAction::Unchanged => "跳过",
+            PlannedAction::Filtered => "过滤",
+            PlannedAction::Delete => "删除",
+        }
+    }
+}
+
+/// 预演中的单个文件
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedFile {
+    pub relative_path: String,
+    pub action: PlannedAction,
+    /// 原始文件大小（已删除的文件为记录中的大小）
+    pub size: u64,
+}
+
+/// 预演结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub input: String,
+    pub output: String,
+    /// 输入目录中找到的文件数
+    pub total_files: usize,
+    pub walk_complete: bool,
+    /// 各类操作的文件数
+    pub counts: BTreeMap<PlannedAction, usize>,
+    /// 需要压缩加密的原始字节数
+    pub bytes_to_process: u64,
+    pub files: Vec<PlannedFile>,
+}
+
+/// 运行摘要，写入 JSON/NDJSON 清单和运行报告
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub input: String,
+    pub output: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_secs: f64,
+    /// 输入目录中找到的文件数
+    pub total_files: usize,
+    pub new: usize,
+    pub updated: usize,
+    /// 未变化而跳过的文件数
+    pub skipped: usize,
+    /// 不在大小/时间过滤范围内而跳过的文件数
+    pub filtered: usize,
+    pub failed: usize,
+    pub deleted: usize,
+    /// 记录的符号链接数
+    pub symlinks: usize,
+    /// 记录的空目录数
+    pub empty_dirs: usize,
+    /// 跳过的特殊文件和符号链接数
+    pub skipped_entries: usize,
+    /// 本次处理的文件的原始总大小
+    pub original_bytes: u64,
+    /// 本次处理的文件的输出总大小
+    pub output_bytes: u64,
+    pub walk_complete: bool,
+    pub interrupted: bool,
+    pub failures: Vec<FailureSummary>,
+}
+
+/// 运行摘要中的失败文件
+#[derive(Debug, Clone, Serialize)]
+pub struct FailureSummary {
+    pub path: String,
+    pub error: String,
+}
+
+/// 还原选项
+#[derive(Debug, Clone)]
+pub struct RestoreOptions {
+    /// 加密目录（加密时的输出目录）
+    pub input: PathBuf,
+    /// 还原目录
+    pub output: PathBuf,
+    /// 解密密码
+    pub password: String,
+}
+
+/// 还原成功的文件
+#[derive(Debug, Clone)]
+pub struct RestoredFile {
+    /// 加密文件路径
+    pub source: PathBuf,
+    /// 还原后的相对路径
+    pub relative_path: String,
+}
+
+/// 还原失败的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreFailureKind {
+    /// 认证失败，且密文与数据库记录一致
+    WrongPassword,
+    /// 文件头无效、数据被截断，或输出哈希与数据库记录不一致
+    Corrupted,
+    /// 其他错误（如 IO 错误）
+    Other,
+}
+
+/// 还原失败的文件
+#[derive(Debug, Clone)]
+pub struct RestoreFailure {
+    pub path: PathBuf,
+    pub kind: RestoreFailureKind,
+    pub error: String,
+}
+
+/// 还原结果
+#[derive(Debug, Clone, Default)]
+pub struct RestoreReport {
+    pub restored: Vec<RestoredFile>,
+    /// 重新创建的空目录和符号链接
+    pub entries: Vec<EntryRecord>,
+    pub failed: Vec<RestoreFailure>,
+}
+
+/// 校验失败的文件
+#[derive(Debug, Clone)]
+pub struct VerifyMismatch {
+    pub relative_path: String,
+    pub reason: String,
+}
+
+/// 校验方式
+#[derive(Debug, Clone)]
+pub enum VerifyMode {
+    /// 重新计算输出文件哈希，与 output_hash 比对
+    Fast,
+    /// 完整解密并解压，与 original_hash 比对
+    Deep { password: String },
+}
+
+impl VerifyMode {
+    fn label(&self) -> &'static str {
+        match self {
+            VerifyMode::Fast => "快速",
+            VerifyMode::Deep { .. } => "深度",
+        }
+    }
+}
+
+/// 校验选项
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// 加密目录（加密时的输出目录）
+    pub output: PathBuf,
+    pub mode: VerifyMode,
+}
+
+/// 校验结果
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// 校验的文件数
+    pub checked: usize,
+    pub mismatched: Vec<VerifyMismatch>,
+}
+
+/// 压缩并加密输入目录（增量处理），每处理完一个文件调用一次 `on_event`
+pub fn encrypt_tree(
+    db: &mut Database,
+    options: &EncryptOptions,
+    on_event: impl Fn(&FileEvent) + Sync,
+) -> Result<EncryptReport> {
+    let input_path = options.input.as_path();
+    let output_path = options.output.as_path();
+    let started_at = chrono::Local::now();
+    let started = Instant::now();
+
+    // 指定的 KDF 与已有密钥文件不一致时不做任何修改
+    check_kdf(output_path, options.kdf)?;
+
+    // 创建输出目录
+    fs::create_dir_all(output_path)?;
+    let encrypt_names = options.encrypt_names || uses_encrypted_names(output_path);
+    if encrypt_names && !uses_encrypted_names(output_path) {
+        for manifest in check_plaintext_artifacts(db, output_path)? {
+            fs::remove_file(&manifest)
+                .context(format!("无法删除明文清单: {}", manifest.display()))?;
+        }
+    }
+
+    // 收集所有未被忽略的文件路径
+    let WalkResult {
+        files: file_paths,
+        symlinks,
+        empty_dirs,
+        skipped: mut skipped_entries,
+        complete: walk_complete,
+    } = walk_encrypt_input(db, options)?;
+
+    // 预检输出路径冲突，有冲突时不处理任何文件
+    let relative_paths: Vec<PathBuf> = file_paths
+        .iter()
+        .filter_map(|p| p.strip_prefix(input_path).ok())
+        .map(Path::to_path_buf)
+        .collect();
+    let case_insensitive = is_case_insensitive(output_path);
+    if !encrypt_names {
+        check_output_collisions(&relative_paths, case_insensitive)?;
+    }
+    let legacy_outputs = unambiguous_legacy_outputs(&relative_paths, case_insensitive);
+
+    // 按规范化后的输入/输出目录隔离数据库记录
+    let input_root = fs::canonicalize(input_path)
+        .context(format!("无法访问输入目录: {}", input_path.display()))?;
+    let output_root = fs::canonicalize(output_path)?;
+    let first_run = db.find_source(&input_root, &output_root)?.is_none();
+    let source_id = db.register_source(&input_root, &output_root)?;
+    // 首次登记来源时接管升级前生成的、输出文件位于该输出目录下的旧记录，升级后不必重新加密
+    if first_run {
+        let adopted = unsourced_records_for_output(db, output_path)?;
+        db.adopt_files(source_id, &adopted)?;
+    }
+
+    // 空目录和符号链接没有输出文件，只记录在数据库中。遍历不完整时保留已有的目录项
+    let entries = collect_entries(input_path, &symlinks, &empty_dirs, &mut skipped_entries);
+    db.sync_entries(source_id, &entries, walk_complete)?;
+
+    // 每次运行只派生一次主密钥
+    let master = MasterKey::load_or_create(output_path, &options.password, options.kdf)?;
+    let kdf = master.kdf();
+    let keys = KeyRing::with_master_key(&options.password, master);
+
+    // 加密文件名时先为每个文件分配对象 ID，并在创建或移动任何对象之前写入索引，
+    // 中途中断时已生成的对象也能按索引还原
+    let object_ids = if encrypt_names {
+        let (object_ids, pending) = assign_object_ids(db, source_id, &relative_paths)?;
+        write_index(output_path, &keys, &build_index(db, output_path, pending)?)?;
+        object_ids
+    } else {
+        HashMap::new()
+    };
+
+    let context = EncryptContext {
+        input_path,
+        output_path,
+        keys: &keys,
+        source_id,
+        encrypt_names,
+        legacy_outputs: &legacy_outputs,
+        object_ids: &object_ids,
+        db: Mutex::new(db),
+        pending_records: Mutex::new(Vec::new()),
+        pending_logs: Mutex::new(Vec::new()),
+        flush: options.flush,
+        flush_state: Mutex::new(FlushState {
+            last_flush: Instant::now(),
+            logs_written: 0,
+        }),
+    };
+    for entry in &skipped_entries {
+        let path = entry.path.strip_prefix(input_path).unwrap_or(&entry.path);
+        queue_log(
+            &context.pending_logs,
+            &encode_path(path),
+            "walk",
+            "skipped",
+            &format!("已跳过: {}", entry.reason),
+        );
+    }
+    let cancelled = || {
+        options
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+    };
+
+    // 使用 Rayon 并行处理文件，处理过程中分批写入数据库
+    let now = SystemTime::now();
+    let skipped = AtomicUsize::new(0);
+    let filtered = AtomicUsize::new(0);
+    let events: Vec<Option<FileEvent>> = file_paths
+        .par_iter()
+        .map(|file_path| {
+            if cancelled() {
+                return Ok(None);
+            }
+            // 不在大小/时间范围内的文件本次不处理，也不视为删除
+            if fs::metadata(file_path).is_ok_and(|m| !options.filter.accepts(&m, now)) {
+                filtered.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+
+            let event = match process_file_with_check(file_path, &context) {
+                Ok(Some(processed)) => Some(FileEvent::Processed(processed)),
+                Ok(None) => {
+                    skipped.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+                Err(e) => {
+                    // 记录错误日志到批量队列
+                    if let Ok(relative_path) = file_path.strip_prefix(input_path) {
+                        queue_log(
+                            &context.pending_logs,
+                            &encode_path(relative_path),
+                            "process",
+                            "error",
+                            &e.to_string(),
+                        );
+                    }
+
+                    Some(FileEvent::Failed(FailedFile {
+                        path: file_path.clone(),
+                        error: e.to_string(),
+                    }))
+                }
+            };
+
+            context.flush(false)?;
+            if let Some(event) = &event {
+                on_event(event);
+            }
+            Ok(event)
+        })
+        .collect::<Result<_>>()?;
+    let events: Vec<FileEvent> = events.into_iter().flatten().collect();
+    let interrupted = cancelled();
+
+    // 删除检测：数据库中存在但本次未找到的文件。遍历不完整或被取消时无法区分删除与未处理，跳过
+    let mut deleted_events = Vec::new();
+    if walk_complete && !interrupted {
+        let mut db = context.db.lock().unwrap();
+        let seen: HashSet<String> = file_paths
+            .iter()
+            .filter_map(|p| p.strip_prefix(input_path).ok())
+            .map(encode_path)
+            .collect();
+        let missing: Vec<FileRecord> = db
+            .get_all_files(source_id)?
+            .into_iter()
+            .filter(|record| !seen.contains(&record.relative_path))
+            .collect();
+
+        let archive_dir = output_path
+            .join(ARCHIVE_DIR)
+            .join(chrono::Local::now().format("%Y%m%d-%H%M%S").to_string());
+        for record in &missing {
+            let event = handle_orphan(
+                record,
+                output_path,
+                &archive_dir,
+                options.orphans,
+                &context.pending_logs,
+            );
+            on_event(&event);
+            deleted_events.push(event);
+        }
+
+        // 输出文件处理失败的记录暂不标记，下次运行时重试
+        let paths: Vec<String> = deleted_events
+            .iter()
+            .filter_map(|event| match event {
+                FileEvent::Deleted(file) => Some(file.relative_path.clone()),
+                _ => None,
+            })
+            .collect();
+        db.mark_deleted(source_id, &paths)?;
+    }
+
+    // 写入剩余的记录和日志
+    context.flush(true)?;
+    let logs_written = context.flush_state.into_inner().unwrap().logs_written;
+
+    let mut processed = Vec::new();
+    let mut failed = Vec::new();
+    let mut deleted = Vec::new();
+    for event in events.into_iter().chain(deleted_events) {
+        match event {
+            FileEvent::Processed(file) => processed.push(file),
+            FileEvent::Failed(file) => failed.push(file),
+            FileEvent::Deleted(file) => deleted.push(file),
+        }
+    }
+
+    let count = |status| processed.iter().filter(|p| p.status == status).count();
+    let summary = RunSummary {
+        input: input_path.display().to_string(),
+        output: output_path.display().to_string(),
+        started_at: started_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        finished_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        duration_secs: started.elapsed().as_secs_f64(),
+        total_files: file_paths.len(),
+        new: count(FileStatus::New),
+        updated: count(FileStatus::Updated),
+        skipped: skipped.into_inner(),
+        filtered: filtered.into_inner(),
+        failed: failed.len(),
+        deleted: deleted.len(),
+        symlinks: symlinks.len(),
+        empty_dirs: empty_dirs.len(),
+        skipped_entries: skipped_entries.len(),
+        original_bytes: processed.iter().map(|p| p.record.original_size).sum(),
+        output_bytes: processed.iter().map(|p| p.record.output_size).sum(),
+        walk_complete,
+        interrupted,
+        failures: failed
+            .iter()
+            .map(|f| FailureSummary {
+                path: f.path.display().to_string(),
+                error: f.error.clone(),
+            })
+            .collect(),
+    };
+
+    let db = context.db.into_inner().unwrap();
+    if encrypt_names {
+        write_index(
+            output_path,
+            &keys,
+            &build_index(db, output_path, Vec::new())?,
+        )?;
+    }
+
+    // 从数据库生成该来源的完整清单，未变化的文件也包含在内。清单以明文保存，加密文件名时不生成
+    let manifest_formats: &[ManifestFormat] = if encrypt_names {
+        &[]
+    } else {
+        &options.manifest_formats
+    };
+    let all_records = db.get_all_files(source_id)?;
+    let mut manifest_paths = Vec::new();
+    for &format in manifest_formats {
+        let path = output_path.join(manifest_file_name(MANIFEST_STEM, format));
+        write_manifest(&path, format, &all_records, &summary)?;
+        manifest_paths.push(path);
+    }
+
+    let mut delta_manifest_paths = Vec::new();
+    if options.delta_manifest {
+        let deleted_paths: HashSet<&str> =
+            deleted.iter().map(|d| d.relative_path.as_str()).collect();
+        let deleted_records: Vec<FileRecord> = db
+            .get_deleted_files(source_id)?
+            .into_iter()
+            .filter(|record| deleted_paths.contains(record.relative_path.as_str()))
+            .collect();
+
+        let changes: Vec<(Change, &FileRecord)> = processed
+            .iter()
+            .map(|p| {
+                let change = match p.status {
+                    FileStatus::New => Change::New,
+                    FileStatus::Updated => Change::Updated,
+                };
+                (change, &p.record)
+            })
+            .chain(
+                deleted_records
+                    .iter()
+                    .map(|record| (Change::Deleted, record)),
+            )
+            .collect();
+
+        for &format in manifest_formats {
+            let path = output_path.join(manifest_file_name(DELTA_MANIFEST_STEM, format));
+            write_delta_manifest(&path, format, &changes, &summary)?;
+            delta_manifest_paths.push(path);
+        }
+    }
+
+    Ok(EncryptReport {
+        total_files: file_paths.len(),
+        processed,
+        failed,
+        deleted,
+        entries,
+        skipped: skipped_entries,
+        walk_complete,
+        interrupted,
+        logs_written,
+        manifest_paths,
+        delta_manifest_paths,
+        encrypt_names,
+        summary,
+        kdf,
+    })
+}
+
+/// 为来源中的每个文件确定对象 ID：沿用记录中已有的 ID，否则分配新的 ID。
+/// 同时返回使用新 ID 的记录，数据库中还没有这些记录，需要预先写入索引
+fn assign_object_ids(
+    db: &Database,
+    source_id: i64,
+    relative_paths: &[PathBuf],
+) -> Result<(HashMap<String, String>, Vec<FileRecord>)> {
+    let mut records: HashMap<String, FileRecord> = db
+        .get_all_files(source_id)?
+        .into_iter()
+        .map(|record| (record.relative_path.clone(), record))
+        .collect();
+
+    let mut object_ids = HashMap::new();
+    let mut pending = Vec::new();
+    for relative_path in relative_paths {
+        let relative_path = encode_path(relative_path);
+        let record = records.remove(&relative_path);
+        let object_id = match record.as_ref().and_then(|r| r.object_id.clone()) {
+            Some(object_id) => object_id,
+            None => {
+                let object_id = new_object_id();
+                pending.push(FileRecord {
+                    object_id: Some(object_id.clone()),
+                    ..record.unwrap_or_else(|| FileRecord {
+                        id: None,
+                        relative_path: relative_path.clone(),
+                        modified_time: String::new(),
+                        original_hash: String::new(),
+                        output_hash: String::new(),
+                        original_size: 0,
+                        output_size: 0,
+                        created_at: String::new(),
+                        object_id: None,
+                    })
+                });
+                object_id
+            }
+        };
+        object_ids.insert(relative_path, object_id);
+    }
+    Ok((object_ids, pending))
+}
+
+/// 从数据库生成输出目录的加密索引（包含输出到该目录的所有来源），
+/// `pending` 为尚未写入数据库、但即将创建对象的记录
+fn build_index(db: &Database, output_path: &Path, pending: Vec<FileRecord>) -> Result<NameIndex> {
+    let mut index = NameIndex::default();
+    for source in sources_for_output(db, output_path)? {
+        index.files.extend(
+            db.get_deleted_files(source.id)?
+                .into_iter()
+                .chain(db.get_all_files(source.id)?)
+                .filter(|record| record.object_id.is_some()),
+        );
+        index.entries.extend(db.get_entries(source.id)?);
+    }
+    index.files.extend(pending);
+    Ok(index)
+}
+
+/// 遍历输入目录，跳过位于输入目录中的输出目录和数据库文件
+fn walk_encrypt_input(db: &Database, options: &EncryptOptions) -> Result<WalkResult> {
+    let db_files: Vec<PathBuf> = db
+        .path()
+        .map(|path| {
+            ["", "-journal", "-wal", "-shm"]
+                .iter()
+                .map(|suffix| {
+                    let mut name = path.clone().into_os_string();
+                    name.push(suffix);
+                    PathBuf::from(name)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let excluded: Vec<&Path> = std::iter::once(options.output.as_path())
+        .chain(db_files.iter().map(PathBuf::as_path))
+        .collect();
+    walk_input(&options.input, &options.filter, options.symlinks, &excluded)
+}
+
+/// 生成空目录和符号链接的目录项，无法记录的条目加入 `skipped`
+fn collect_entries(
+    input_path: &Path,
+    symlinks: &[PathBuf],
+    empty_dirs: &[PathBuf],
+    skipped: &mut Vec<SkippedEntry>,
+) -> Vec<EntryRecord> {
+    let mut entries = Vec::new();
+    for (path, kind) in empty_dirs
+        .iter()
+        .map(|path| (path, EntryKind::Dir))
+        .chain(symlinks.iter().map(|path| (path, EntryKind::Symlink)))
+    {
+        let entry = (|| -> Result<EntryRecord> {
+            let relative_path = encode_path(path.strip_prefix(input_path)?);
+            let target = match kind {
+                EntryKind::Dir => None,
+                EntryKind::Symlink => Some(encode_path(&fs::read_link(path)?)),
+            };
+            Ok(EntryRecord {
+                relative_path,
+                kind,
+                target,
+            })
+        })();
+
+        match entry {
+            Ok(entry) => entries.push(entry),
+            Err(e) => skipped.push(SkippedEntry {
+                path: path.clone(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+    entries.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+    entries
+}
+
+/// 预演一次增量加密：使用与 `encrypt_tree` 相同的判断逻辑，不写入输出文件和数据库记录
+pub fn plan_tree(db: &Database, options: &EncryptOptions) -> Result<Plan> {
+    let input_path = options.input.as_path();
+    let output_path = options.output.as_path();
+
+    let WalkResult {
+        files: file_paths,
+        complete: walk_complete,
+        ..
+    } = walk_encrypt_input(db, options)?;
+
+    let relative_paths: Vec<PathBuf> = file_paths
+        .iter()
+        .filter_map(|p| p.strip_prefix(input_path).ok())
+        .map(Path::to_path_buf)
+        .collect();
+    check_kdf(output_path, options.kdf)?;
+    let encrypt_names = options.encrypt_names || uses_encrypted_names(output_path);
+    if encrypt_names && !uses_encrypted_names(output_path) {
+        check_plaintext_artifacts(db, output_path)?;
+    }
+    if !encrypt_names {
+        check_output_collisions(
+            &relative_paths,
+            output_path.is_dir() && is_case_insensitive_readonly(output_path),
+        )?;
+    }
+
+    // 输出目录不存在时数据库中也不会有该来源的记录
+    let source = match fs::canonicalize(output_path) {
+        Ok(output) => db.find_source(&fs::canonicalize(input_path)?, &output)?,
+        Err(_) => None,
+    };
+    // 首次运行时与 encrypt_tree 一致，使用将被接管的旧记录
+    let records: HashMap<String, FileRecord> = match &source {
+        Some(source) => db.get_all_files(source.id)?,
+        None if output_path.is_dir() => unsourced_records_for_output(db, output_path)?,
+        None => Vec::new(),
+    }
+    .into_iter()
+    .map(|record| (record.relative_path.clone(), record))
+    .collect();
+
+    let now = SystemTime::now();
+    let mut files: Vec<PlannedFile> = file_paths
+        .par_iter()
+        .map(|file_path| {
+            let relative_path = encode_path(file_path.strip_prefix(input_path)?);
+            let metadata = fs::metadata(file_path)?;
+            let action = if options.filter.accepts(&metadata, now) {
+                check_file(
+                    file_path,
+                    records.get(&relative_path),
+                    output_path,
+                    encrypt_names,
+                )?
+            } else {
+                PlannedAction::Filtered
+            };
+            Ok(PlannedFile {
+                relative_path,
+                action,
+                size: metadata.len(),
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    // 与 encrypt_tree 一致：遍历不完整时不做删除检测
+    if walk_complete {
+        let seen: HashSet<&str> = files.iter().map(|f| f.relative_path.as_str()).collect();
+        let deleted: Vec<PlannedFile> = records
+            .values()
+            .filter(|record| !seen.contains(record.relative_path.as_str()))
+            .map(|record| PlannedFile {
+                relative_path: record.relative_path.clone(),
+                action: PlannedAction::Delete,
+                size: record.original_size,
+            })
+            .collect();
+        files.extend(deleted);
+    }
+    files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+    let mut counts = BTreeMap::new();
+    for file in &files {
+        *counts.entry(file.action).or_insert(0) += 1;
+    }
+    let bytes_to_process = files
+        .iter()
+        .filter(|f| f.action.needs_processing())
+        .map(|f| f.size)
+        .sum();
+
+    Ok(Plan {
+        input: input_path.display().to_string(),
+        output: output_path.display().to_string(),
+        total_files: file_paths.len(),
+        walk_complete,
+        counts,
+        bytes_to_process,
+        files,
+    })
+}
+
+/// 将加密目录还原为原始目录结构
+pub fn restore_tree(db: &Database, options: &RestoreOptions) -> Result<RestoreReport> {
+    let encrypted_path = options.input.as_path();
+    let restore_path = options.output.as_path();
+    fs::create_dir_all(restore_path)?;
+
+    // 存在密钥文件时先校验密码，密码错误直接返回
+    let keys = load_keys(encrypted_path, &options.password)?;
+    let encrypted_names = uses_encrypted_names(encrypted_path);
+    let index = load_index(encrypted_path, &keys)?.unwrap_or_default();
+
+    // 从数据库建立 输出文件相对路径 -> 原始记录 的映射，用于恢复原始文件名
+    // （包含已删除的记录，以便还原保留下来的孤立输出文件）
+    // 加密文件名时以加密索引为准，没有数据库也能还原
+    let mut catalog: HashMap<PathBuf, FileRecord> = HashMap::new();
+    let mut entries: BTreeMap<String, EntryRecord> = BTreeMap::new();
+    let mut legacy_records = Vec::new();
+    for source in sources_for_output(db, encrypted_path)? {
+        for entry in db.get_entries(source.id)? {
+            entries.insert(entry.relative_path.clone(), entry);
+        }
+        for record in db
+            .get_deleted_files(source.id)?
+            .into_iter()
+            .chain(db.get_all_files(source.id)?)
+        {
+            if record.object_id.is_none() {
+                legacy_records.push(record.clone());
+            }
+            catalog.insert(record_output_relative_path(&record), record);
+        }
+    }
+    for record in index.files {
+        catalog.insert(record_output_relative_path(&record), record);
+    }
+    for entry in index.entries {
+        entries.insert(entry.relative_path.clone(), entry);
+    }
+
+    // 升级前生成的文件使用旧的命名方式，只在没有冲突时用于恢复文件名。
+    // 旧命名 -> 新命名，新命名的文件也存在时以新文件为准，跳过旧文件
+    let mut legacy_aliases: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for record in legacy_records {
+        let legacy = legacy_output_relative_path(&record.relative_path);
+        if catalog.contains_key(&legacy) {
+            continue;
+        }
+        legacy_aliases.insert(legacy.clone(), record_output_relative_path(&record));
+        catalog.insert(legacy, record);
+    }
+
+    // 跳过归档目录
+    let encrypted_files: Vec<PathBuf> = WalkDir::new(encrypted_path)
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || e.file_name() != ARCHIVE_DIR)
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .filter(|p| {
+            p.to_string_lossy()
+                .ends_with(&format!(".{}", OUTPUT_SUFFIX))
+        })
+        .filter(|p| {
+            let alias = p
+                .strip_prefix(encrypted_path)
+                .ok()
+                .and_then(|relative| legacy_aliases.get(relative));
+            !alias.is_some_and(|current| encrypted_path.join(current).is_file())
+        })
+        .collect();
+
+    let results: Vec<(PathBuf, Result<String>)> = encrypted_files
+        .par_iter()
+        .map(|file_path| {
+            let result = restore_file(
+                file_path,
+                encrypted_path,
+                restore_path,
+                &keys,
+                &catalog,
+                encrypted_names,
+            );
+            (file_path.clone(), result)
+        })
+        .collect();
+
+    let mut report = RestoreReport::default();
+    for (path, result) in results {
+        match result {
+            Ok(relative_path) => report.restored.push(RestoredFile {
+                source: path,
+                relative_path,
+            }),
+            Err(e) => {
+                let kind = match e.downcast_ref::<ContainerError>() {
+                    Some(ContainerError::WrongPassword) => RestoreFailureKind::WrongPassword,
+                    Some(ContainerError::Corrupted(_)) => RestoreFailureKind::Corrupted,
+                    Some(ContainerError::MissingKeyFile) | None => RestoreFailureKind::Other,
+                };
+                report.failed.push(RestoreFailure {
+                    path,
+                    kind,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    // 文件还原后再创建空目录和符号链接
+    for entry in entries.into_values() {
+        match restore_entry(&entry, restore_path) {
+            Ok(()) => report.entries.push(entry),
+            Err(e) => report.failed.push(RestoreFailure {
+                path: restore_path.join(decode_path(&entry.relative_path)),
+                kind: RestoreFailureKind::Other,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// 校验输出目录中的文件与数据库记录是否一致，结果写入日志
+pub fn verify_tree(db: &mut Database, options: &VerifyOptions) -> Result<VerifyReport> {
+    let output = options.output.as_path();
+    let sources = sources_for_output(db, output)?;
+    if sources.is_empty() {
+        bail!("数据库中没有输出目录 {} 的记录", output.display());
+    }
+    let mut records = Vec::new();
+    for source in &sources {
+        records.extend(db.get_all_files(source.id)?);
+    }
+
+    // 深度校验需要解密，先校验密码
+    let keys = match &options.mode {
+        VerifyMode::Fast => None,
+        VerifyMode::Deep { password } => Some(load_keys(output, password)?),
+    };
+
+    let mismatched: Vec<VerifyMismatch> = records
+        .par_iter()
+        .filter_map(|record| {
+            let output_file = locate_output(output, record);
+            let reason = if !output_file.is_file() {
+                "输出文件不存在".to_string()
+            } else if let Some(keys) = &keys {
+                match decrypted_hash(&output_file, output, keys) {
+                    Ok(hash) if hash == record.original_hash => return None,
+                    Ok(_) => "解密后的内容与原始文件哈希不一致".to_string(),
+                    Err(e) => format!("解密失败: {}", e),
+                }
+            } else {
+                match compute_file_hash_simd(&output_file) {
+                    Ok(hash) if hash == record.output_hash => return None,
+                    Ok(_) => "输出文件哈希不一致".to_string(),
+                    Err(e) => format!("读取失败: {}", e),
+                }
+            };
+            Some(VerifyMismatch {
+                relative_path: record.relative_path.clone(),
+                reason,
+            })
+        })
+        .collect();
+
+    // 每个不一致的文件一条日志，另加一条汇总日志
+    let pending_logs = Mutex::new(Vec::new());
+    for mismatch in &mismatched {
+        queue_log(
+            &pending_logs,
+            &mismatch.relative_path,
+            "verify",
+            "mismatch",
+            &mismatch.reason,
+        );
+    }
+    queue_log(
+        &pending_logs,
+        &output.to_string_lossy(),
+        "verify",
+        if mismatched.is_empty() {
+            "ok"
+        } else {
+            "failed"
+        },
+        &format!(
+            "{}校验 {} 个文件，{} 个不一致",
+            options.mode.label(),
+            records.len(),
+            mismatched.len()
+        ),
+    );
+    db.batch_add_logs(&pending_logs.into_inner().unwrap())?;
+
+    Ok(VerifyReport {
+        checked: records.len(),
+        mismatched,
+    })
+}
+
+/// 查找输出到 `output` 的来源
+///
+/// 数据库保存在输出目录中（catalog.db）且整个目录被移动过时，记录中的输出目录已不匹配，
+/// 此时使用该数据库中的所有来源
+fn sources_for_output(db: &Database, output: &Path) -> Result<Vec<SourceRecord>> {
+    let output = fs::canonicalize(output)?;
+    let sources = db.find_sources_by_output(&output)?;
+    if !sources.is_empty() {
+        return Ok(sources);
+    }
+
+    let catalog_in_output = db
+        .path()
+        .and_then(|path| fs::canonicalize(path).ok())
+        .is_some_and(|path| path.parent() == Some(output.as_path()));
+    if catalog_in_output {
+        db.get_sources()
+    } else {
+        Ok(sources)
+    }
+}
+
+/// 读取密钥文件并校验密码，没有密钥文件时只使用密码
+fn load_keys(dir: &Path, password: &str) -> Result<KeyRing> {
+    Ok(match MasterKey::load(dir, password)? {
+        Some(master) => KeyRing::with_master_key(password, master),
+        None => KeyRing::from_password(password),
+    })
+}
+
+/// 解密输出文件并计算原始内容的哈希（不写入磁盘）
+fn decrypted_hash(output_file: &Path, output_path: &Path, keys: &KeyRing) -> Result<String> {
+    let relative = output_file.strip_prefix(output_path)?;
+    let mut hasher = Sha256::new();
+    decrypt_to_writer(output_file, &mut hasher, keys, &encode_path(relative))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// 原始相对路径（数据库中的编码形式）对应的输出文件相对路径（在原文件名后追加 `.zstd.enc`）
+pub fn output_relative_path(relative_path: &str) -> PathBuf {
+    let mut path = decode_path(relative_path).into_os_string();
+    path.push(format!(".{}", OUTPUT_SUFFIX));
+    PathBuf::from(path)
+}
+
+/// 旧版本的输出文件相对路径（替换原扩展名），仅用于识别升级前生成的文件
+fn legacy_output_relative_path(relative_path: &str) -> PathBuf {
+    decode_path(relative_path).with_extension(OUTPUT_SUFFIX)
+}
+
+/// 记录对应的输出文件相对路径：加密文件名时为对象路径
+fn record_output_relative_path(record: &FileRecord) -> PathBuf {
+    match &record.object_id {
+        Some(object_id) => object_relative_path(object_id),
+        None => output_relative_path(&record.relative_path),
+    }
+}
+
+/// 升级前的旧记录没有关联来源：输出文件位于该输出目录下、且哈希与记录一致的记录属于该输出目录
+fn unsourced_records_for_output(db: &Database, output_path: &Path) -> Result<Vec<FileRecord>> {
+    Ok(db
+        .get_unsourced_files()?
+        .into_par_iter()
+        .filter(|record| {
+            compute_file_hash_simd(&locate_output(output_path, record))
+                .is_ok_and(|hash| hash == record.output_hash)
+        })
+        .collect())
+}
+
+/// 查找记录对应的输出文件：新命名不存在时尝试旧命名
+fn locate_output(output_path: &Path, record: &FileRecord) -> PathBuf {
+    let output_file = output_path.join(record_output_relative_path(record));
+    let legacy = output_path.join(legacy_output_relative_path(&record.relative_path));
+    if record.object_id.is_none() && !output_file.is_file() && legacy.is_file() {
+        legacy
+    } else {
+        output_file
+    }
+}
+
+/// 映射到同一输出路径的一组输入
+#[derive(Debug, Clone)]
+pub struct OutputCollision {
+    /// 冲突的输出路径（以 `/` 分隔）
+    pub output: String,
+    /// 冲突的输入文件
+    pub inputs: Vec<String>,
+}
+
+/// 输出路径冲突（预检失败，未处理任何文件）
+#[derive(Debug)]
+pub struct CollisionError(pub Vec<OutputCollision>);
+
+impl fmt::Display for CollisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "发现 {} 处输出路径冲突，已中止:", self.0.len())?;
+        for collision in &self.0 {
+            write!(
+                f,
+                "\n  {} ← {}",
+                collision.output,
+                collision.inputs.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CollisionError {}
+
+/// 输出目录中由程序使用、不能被输出文件占用的名称
+/// （另外还有各种格式的清单文件名）
+const RESERVED_NAMES: &[&str] = &[
+    KEY_FILE_NAME,
+    CATALOG_FILE_NAME,
+    ARCHIVE_DIR,
+    OBJECTS_DIR,
+    INDEX_FILE_NAME,
+];
+
+/// 切换到加密文件名前检查输出目录中以明文路径保存的内容：
+/// 清单可以重新生成，返回后由调用方删除；归档目录、目录数据库和保留下来的孤立输出文件需要用户自行处理
+fn check_plaintext_artifacts(db: &Database, output_path: &Path) -> Result<Vec<PathBuf>> {
+    for name in [ARCHIVE_DIR, CATALOG_FILE_NAME] {
+        let path = output_path.join(name);
+        if path.exists() {
+            bail!(
+                "输出目录中的 {} 包含明文路径，无法切换到加密文件名，请先移走该文件或目录",
+                path.display()
+            );
+        }
+    }
+
+    // 源文件已删除的输出文件无法重新生成，路径又受认证保护，不能直接移动到对象目录
+    if output_path.is_dir() {
+        let mut kept = Vec::new();
+        for source in sources_for_output(db, output_path)? {
+            kept.extend(
+                db.get_deleted_files(source.id)?
+                    .iter()
+                    .filter(|record| record.object_id.is_none())
+                    .map(|record| locate_output(output_path, record))
+                    .filter(|path| path.is_file()),
+            );
+        }
+        if let Some(first) = kept.first() {
+            bail!(
+                "输出目录中保留了 {} 个源文件已删除的输出文件（如 {}），它们使用明文路径，无法切换到加密文件名，请先删除或移走这些文件",
+                kept.len(),
+                first.display()
+            );
+        }
+    }
+
+    Ok([MANIFEST_STEM, DELTA_MANIFEST_STEM]
+        .into_iter()
+        .flat_map(|stem| ManifestFormat::ALL.map(|format| manifest_file_name(stem, format)))
+        .map(|name| output_path.join(name))
+        .filter(|path| path.is_file())
+        .collect())
+}
+
+/// 检查输入文件映射到的输出路径是否冲突：
+/// 同名输出文件、输出文件与输出目录同名，以及占用程序保留的名称
+fn check_output_collisions(relative_paths: &[PathBuf], case_insensitive: bool) -> Result<()> {
+    let normalize = |path: String| {
+        if case_insensitive {
+            path.to_lowercase()
+        } else {
+            path
+        }
+    };
+
+    // 输出路径 -> (显示用路径, 输入文件)
+    let mut files: HashMap<String, (String, Vec<String>)> = HashMap::new();
+    // 输出目录 -> 位于其中的输入文件
+    let mut dirs: HashMap<String, Vec<String>> = HashMap::new();
+    for relative_path in relative_paths {
+        let input = encode_path(relative_path);
+        let output = encode_path(&output_relative_path(&input));
+        files
+            .entry(normalize(output.clone()))
+            .or_insert_with(|| (output.clone(), Vec::new()))
+            .1
+            .push(input.clone());
+
+        let mut dir = String::new();
+        for component in Path::new(&output).parent().into_iter().flat_map(Path::iter) {
+            if !dir.is_empty() {
+                dir.push('/');
+            }
+            dir.push_str(&component.to_string_lossy());
+            dirs.entry(normalize(dir.clone()))
+                .or_default()
+                .push(input.clone());
+        }
+    }
+
+    let manifest_names = [MANIFEST_STEM, DELTA_MANIFEST_STEM]
+        .into_iter()
+        .flat_map(|stem| ManifestFormat::ALL.map(|format| manifest_file_name(stem, format)));
+    let reserved: Vec<String> = RESERVED_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .chain(manifest_names)
+        .map(normalize)
+        .collect();
+
+    let mut collisions = Vec::new();
+    for (key, (output, mut inputs)) in files {
+        if let Some(nested) = dirs.get(&key) {
+            inputs.extend(nested.iter().cloned());
+        }
+        if inputs.len() > 1 {
+            inputs.sort();
+            collisions.push(OutputCollision { output, inputs });
+        }
+    }
+    for (dir, mut inputs) in dirs {
+        if !dir.contains('/') && reserved.contains(&dir) {
+            inputs.sort();
+            collisions.push(OutputCollision {
+                output: format!("{} (保留名称)", dir),
+                inputs,
+            });
+        }
+    }
+
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    collisions.sort_by(|a, b| a.output.cmp(&b.output));
+    Err(CollisionError(collisions).into())
+}
+
+/// 检测目录所在的文件系统是否不区分大小写
+fn is_case_insensitive(dir: &Path) -> bool {
+    let probe = dir.join(".hbsx-case-probe");
+    if fs::write(&probe, b"").is_err() {
+        return false;
+    }
+    let insensitive = dir.join(".HBSX-CASE-PROBE").exists();
+    let _ = fs::remove_file(&probe);
+    insensitive
+}
+
+/// 不写入文件的大小写检测（用于预演）：输出目录名改变大小写后仍能访问时视为不区分大小写。
+/// 目录名不含字母时无法判断，按区分大小写处理
+fn is_case_insensitive_readonly(dir: &Path) -> bool {
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+    let (Some(parent), Some(name)) = (dir.parent(), dir.file_name().and_then(|n| n.to_str()))
+    else {
+        return false;
+    };
+    let swapped: String = name
+        .chars()
+        .flat_map(|c| {
+            if c.is_uppercase() {
+                c.to_lowercase().collect::<Vec<_>>()
+            } else {
+                c.to_uppercase().collect()
+            }
+        })
+        .collect();
+    swapped != name && parent.join(swapped).is_dir()
+}
+
+/// 一次加密运行中所有文件共享的状态
+struct EncryptContext<'a> {
+    input_path: &'a Path,
+    output_path: &'a Path,
+    keys: &'a KeyRing,
+    source_id: i64,
+    encrypt_names: bool,
+    /// 可以直接删除的旧命名输出文件
+    legacy_outputs: &'a HashSet<PathBuf>,
+    /// 加密文件名时每个文件（编码后的相对路径）使用的对象 ID
+    object_ids: &'a HashMap<String, String>,
+    db: Mutex<&'a mut Database>,
+    /// 等待写入数据库的记录和日志
+    pending_records: Mutex<Vec<FileRecord>>,
+    pending_logs: Mutex<Vec<LogRecord>>,
+    flush: FlushPolicy,
+    flush_state: Mutex<FlushState>,
+}
+
+struct FlushState {
+    last_flush: Instant,
+    logs_written: usize,
+}
+
+impl EncryptContext<'_> {
+    /// 队列达到批量大小或距上次写入超过间隔时写入数据库，`force` 为 true 时总是写入
+    fn flush(&self, force: bool) -> Result<()> {
+        let mut state = self.flush_state.lock().unwrap();
+        let mut records = self.pending_records.lock().unwrap();
+        let mut logs = self.pending_logs.lock().unwrap();
+
+        let due = records.len() + logs.len() >= self.flush.batch_size
+            || state.last_flush.elapsed() >= self.flush.interval;
+        if !force && !due {
+            return Ok(());
+        }
+
+        let records = mem::take(&mut *records);
+        let logs = mem::take(&mut *logs);
+        let mut db = self.db.lock().unwrap();
+        db.batch_upsert_files(self.source_id, &records)?;
+        db.batch_add_logs(&logs)?;
+
+        state.last_flush = Instant::now();
+        state.logs_written += logs.len();
+        Ok(())
+    }
+}
+
+/// 检查并处理文件（增量处理逻辑）
+fn process_file_with_check(
+    file_path: &Path,
+    context: &EncryptContext,
+) -> Result<Option<ProcessedFile>> {
+    let pending_logs = &context.pending_logs;
+    let relative_path = encode_path(file_path.strip_prefix(context.input_path)?);
+
+    // 检查数据库中是否存在该文件
+    let existing_record = context
+        .db
+        .lock()
+        .unwrap()
+        .file_exists(context.source_id, &relative_path)?;
+
+    let action = check_file(
+        file_path,
+        existing_record.as_ref(),
+        context.output_path,
+        context.encrypt_names,
+    )?;
+    let log = match action {
+        PlannedAction::New => Some(("new", "新文件")),
+        PlannedAction::Changed => Some(("changed", "文件已变化 (修改时间和哈希均不同)")),
+        PlannedAction::MissingOutput => Some(("missing", "输出文件不存在，重新处理")),
+        PlannedAction::Touched => Some(("skip", "文件未实际变化 (仅修改时间变化)")),
+        _ => None,
+    };
+    if let Some((status, message)) = log {
+        queue_log(pending_logs, &relative_path, "check", status, message);
+    }
+
+    if !action.needs_processing() {
+        if let Some(existing) = &existing_record {
+            rename_legacy_output(context.output_path, existing, pending_logs);
+        }
+        return Ok(None);
+    }
+
+    // 加密文件名时使用预先分配并写入索引的对象 ID，更新时覆盖原对象
+    let object_id = if context.encrypt_names {
+        let object_id = context.object_ids.get(&relative_path);
+        Some(object_id.cloned().context("文件没有分配对象 ID")?)
+    } else {
+        None
+    };
+
+    // 执行实际的处理
+    match process_file(
+        file_path,
+        context.input_path,
+        context.output_path,
+        context.keys,
+        object_id,
+    ) {
+        Ok(record) => {
+            remove_previous_output(
+                context.output_path,
+                existing_record.as_ref(),
+                &record,
+                context.legacy_outputs,
+            );
+
+            // 添加到批量写入队列
+            context.pending_records.lock().unwrap().push(record.clone());
+
+            // 记录成功日志到队列
+            queue_log(
+                pending_logs,
+                &relative_path,
+                "process",
+                "success",
+                "文件处理成功",
+            );
+
+            let status = if existing_record.is_some() {
+                FileStatus::Updated
+            } else {
+                FileStatus::New
+            };
+
+            Ok(Some(ProcessedFile { record, status }))
+        }
+        Err(e) => {
+            // 记录失败日志到队列
+            queue_log(
+                pending_logs,
+                &relative_path,
+                "process",
+                "failed",
+                &e.to_string(),
+            );
+            Err(e)
+        }
+    }
+}
+
+/// 按修改时间和哈希判断文件是否需要处理
+fn check_file(
+    file_path: &Path,
+    existing: Option<&FileRecord>,
+    output_path: &Path,
+    encrypt_names: bool,
+) -> Result<PlannedAction> {
+    let Some(existing) = existing else {
+        return Ok(PlannedAction::New);
+    };
+
+    // 修改时间不同时进一步检查 hash（可能只是 touch 了文件）
+    let touched = !same_modified_time(&existing.modified_time, &get_modified_time(file_path)?);
+    if touched && existing.original_hash != compute_file_hash_simd(file_path)? {
+        return Ok(PlannedAction::Changed);
+    }
+
+    // 记录存在但输出文件缺失（被删除，或切换了文件名模式）时重新处理。旧版本命名的文件视为存在
+    if existing.object_id.is_some() != encrypt_names
+        || !locate_output(output_path, existing).is_file()
+    {
+        return Ok(PlannedAction::MissingOutput);
+    }
+
+    Ok(if touched {
+        PlannedAction::Touched
+    } else {
+        PlannedAction::Unchanged
+    })
+}
+
+/// 处理源文件已删除的记录：按策略保留、删除或归档输出文件，并记录日志
+fn handle_orphan(
+    record: &FileRecord,
+    output_path: &Path,
+    archive_dir: &Path,
+    policy: OrphanPolicy,
+    pending_logs: &Mutex<Vec<LogRecord>>,
+) -> FileEvent {
+    let output_file = locate_output(output_path, record);
+    let output_relative = output_file
+        .strip_prefix(output_path)
+        .unwrap_or(&output_file)
+        .to_path_buf();
+
+    let result = if !output_file.is_file() {
+        Ok((None, "源文件已删除 (输出文件不存在)".to_string()))
+    } else {
+        match policy {
+            OrphanPolicy::Keep => Ok((Some(output_file.clone()), "源文件已删除".to_string())),
+            OrphanPolicy::Remove => fs::remove_file(&output_file)
+                .map(|_| (None, "源文件已删除，输出文件已移除".to_string())),
+            OrphanPolicy::Archive => {
+                let target = archive_dir.join(&output_relative);
+                target
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::rename(&output_file, &target))
+                    .map(|_| {
+                        let message =
+                            format!("源文件已删除，输出文件已归档到 {}", target.display());
+                        (Some(target), message)
+                    })
+            }
+        }
+    };
+
+    match result {
+        Ok((output, message)) => {
+            queue_log(
+                pending_logs,
+                &record.relative_path,
+                "delete",
+                "deleted",
+                &message,
+            );
+            FileEvent::Deleted(DeletedFile {
+                relative_path: record.relative_path.clone(),
+                output,
+            })
+        }
+        Err(e) => {
+            let error = format!("处理孤立输出文件失败: {}", e);
+            queue_log(
+                pending_logs,
+                &record.relative_path,
+                "delete",
+                "failed",
+                &error,
+            );
+            FileEvent::Failed(FailedFile {
+                path: output_file,
+                error,
+            })
+        }
+    }
+}
+
+/// 删除重新处理前的旧输出文件（旧版本命名，或切换文件名模式前的位置）。
+/// 哈希与记录一致时确认属于该文件；旧命名的文件没有记录时，只在不与其他输入冲突时删除
+fn remove_previous_output(
+    output_path: &Path,
+    existing: Option<&FileRecord>,
+    record: &FileRecord,
+    legacy_outputs: &HashSet<PathBuf>,
+) {
+    let current = output_path.join(record_output_relative_path(record));
+    let legacy = legacy_output_relative_path(&record.relative_path);
+    let candidates = existing
+        .map(|existing| (record_output_relative_path(existing), false))
+        .into_iter()
+        .chain([(legacy.clone(), legacy_outputs.contains(&legacy))]);
+    for (previous, unambiguous) in candidates {
+        let previous = output_path.join(previous);
+        if previous == current || !previous.is_file() {
+            continue;
+        }
+        let owned = unambiguous
+            || existing.is_some_and(|existing| {
+                compute_file_hash_simd(&previous).is_ok_and(|hash| hash == existing.output_hash)
+            });
+        if owned && fs::remove_file(&previous).is_ok() {
+            remove_empty_parents(&previous, output_path);
+        }
+    }
+}
+
+/// 未变化的文件沿用旧版本命名的输出文件时改为新名称。旧版本的容器不绑定路径，无需重新加密；
+/// 仅当哈希与记录一致，确认属于该文件时
+fn rename_legacy_output(
+    output_path: &Path,
+    existing: &FileRecord,
+    pending_logs: &Mutex<Vec<LogRecord>>,
+) {
+    let output_file = locate_output(output_path, existing);
+    let current = output_path.join(record_output_relative_path(existing));
+    if output_file == current
+        || !compute_file_hash_simd(&output_file).is_ok_and(|hash| hash == existing.output_hash)
+    {
+        return;
+    }
+    if fs::rename(&output_file, &current).is_ok() {
+        queue_log(
+            pending_logs,
+            &existing.relative_path,
+            "check",
+            "renamed",
+            "旧版本命名的输出文件已改为新名称",
+        );
+    }
+}
+
+/// 旧命名方式（替换扩展名）下只对应一个输入、且不是任何输入新输出路径的文件，
+/// 这些旧文件在重新加密时可以直接删除
+fn unambiguous_legacy_outputs(
+    relative_paths: &[PathBuf],
+    case_insensitive: bool,
+) -> HashSet<PathBuf> {
+    let normalize = |path: &Path| {
+        let path = encode_path(path);
+        if case_insensitive {
+            path.to_lowercase()
+        } else {
+            path
+        }
+    };
+
+    let mut claims: HashMap<String, (PathBuf, usize)> = HashMap::new();
+    let mut outputs = HashSet::new();
+    for relative_path in relative_paths {
+        let input = encode_path(relative_path);
+        outputs.insert(normalize(&output_relative_path(&input)));
+        let legacy = legacy_output_relative_path(&input);
+        claims.entry(normalize(&legacy)).or_insert((legacy, 0)).1 += 1;
+    }
+    claims
+        .into_iter()
+        .filter(|(key, (_, count))| *count == 1 && !outputs.contains(key))
+        .map(|(_, (legacy, _))| legacy)
+        .collect()
+}
+
+/// 删除 `path` 所在的空目录，直到 `root` 为止（切换到加密文件名后不留下原目录结构）
+fn remove_empty_parents(path: &Path, root: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// 将日志添加到队列（用于批量写入）
+fn queue_log(
+    pending_logs: &Mutex<Vec<LogRecord>>,
+    file_path: &str,
+    action: &str,
+    status: &str,
+    message: &str,
+) {
+    let log = LogRecord {
+        file_path: file_path.to_string(),
+        action: action.to_string(),
+        status: status.to_string(),
+        message: message.to_string(),
+        timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    pending_logs.lock().unwrap().push(log);
+}
+
+/// 压缩加密单个文件并生成文件记录，指定 `object_id` 时输出为对象路径（加密文件名）
+pub fn process_file(
+    file_path: &Path,
+    input_path: &Path,
+    output_path: &Path,
+    keys: &KeyRing,
+    object_id: Option<String>,
+) -> Result<FileRecord> {
+    let relative_path = encode_path(file_path.strip_prefix(input_path)?);
+
+    // 获取原始文件大小
+    let original_size = fs::metadata(file_path)?.len();
+
+    // 并行计算原始文件 hash（使用 SIMD 加速）
+    let original_hash = compute_file_hash_simd(file_path)?;
+
+    // 获取修改时间
+    let modified_time = get_modified_time(file_path)?;
+
+    // 压缩 + 加密
+    let output_relative = match &object_id {
+        Some(object_id) => object_relative_path(object_id),
+        None => output_relative_path(&relative_path),
+    };
+    let output_file_path = output_path.join(&output_relative);
+
+    // 确保输出文件的父目录存在
+    if let Some(parent) = output_file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    compress_and_encrypt_mt(
+        file_path,
+        &output_file_path,
+        keys,
+        &encode_path(&output_relative),
+    )?;
+
+    // 获取输出文件大小
+    let output_size = fs::metadata(&output_file_path)?.len();
+
+    // 计算输出文件 hash
+    let output_hash = compute_file_hash_simd(&output_file_path)?;
+
+    Ok(FileRecord {
+        id: None,
+        relative_path,
+        modified_time,
+        original_hash,
+        output_hash,
+        original_size,
+        output_size,
+        created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        object_id,
+    })
+}
+
+/// 还原单个加密文件，返回还原后的相对路径
+fn restore_file(
+    file_path: &Path,
+    encrypted_path: &Path,
+    restore_path: &Path,
+    keys: &KeyRing,
+    catalog: &HashMap<PathBuf, FileRecord>,
+    encrypted_names: bool,
+) -> Result<String> {
+    let output_relative = file_path.strip_prefix(encrypted_path)?;
+    let record = catalog.get(output_relative);
+
+    // 优先使用数据库中记录的原始路径，否则去掉 .zstd.enc 后缀。
+    // 加密文件名时对象 ID 不是原始路径，索引中没有的对象无法还原
+    let relative_path = match record {
+        Some(record) => record.relative_path.clone(),
+        None if encrypted_names && output_relative.starts_with(OBJECTS_DIR) => {
+            bail!("对象不在加密索引中，无法确定原始路径");
+        }
+        None => {
+            let name = encode_path(output_relative);
+            match name.strip_suffix(&format!(".{}", OUTPUT_SUFFIX)) {
+                Some(stripped) => stripped.to_string(),
+                None => name,
+            }
+        }
+    };
+
+    let target = restore_path.join(decode_relative_path(&relative_path)?);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match decrypt_and_decompress(file_path, &target, keys, &encode_path(output_relative)) {
+        Ok(()) => Ok(relative_path),
+        Err(e) => {
+            // 解密失败时，若输出哈希与数据库不一致，说明文件本身已损坏
+            if let (Some(ContainerError::WrongPassword), Some(record)) =
+                (e.downcast_ref::<ContainerError>(), record)
+                && compute_file_hash_simd(file_path)? != record.output_hash
+            {
+                return Err(
+                    ContainerError::Corrupted("输出文件哈希与数据库记录不一致".into()).into(),
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// 重新创建空目录或符号链接
+fn restore_entry(entry: &EntryRecord, restore_path: &Path) -> Result<()> {
+    let target = restore_path.join(decode_relative_path(&entry.relative_path)?);
+    match entry.kind {
+        EntryKind::Dir => fs::create_dir_all(&target)?,
+        EntryKind::Symlink => {
+            let link = entry.target.as_deref().context("符号链接缺少目标路径")?;
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // 只替换已存在的符号链接，不覆盖其他文件
+            match fs::symlink_metadata(&target) {
+                Ok(metadata) if metadata.file_type().is_symlink() => fs::remove_file(&target)?,
+                Ok(_) => bail!("目标已存在且不是符号链接: {}", target.display()),
+                Err(_) => {}
+            }
+            create_symlink(&decode_path(link), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(link: &Path, target: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(link, target)
+        .context(format!("无法创建符号链接: {}", target.display()))
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_link: &Path, target: &Path) -> Result<()> {
+    bail!("当前平台不支持还原符号链接: {}", target.display())
+}
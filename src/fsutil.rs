@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// 临时文件名中的标记，格式为 `.<文件名>.hbsx-tmp-<随机数>`
+pub const TEMP_FILE_MARKER: &str = ".hbsx-tmp-";
+
+/// 原子写入的文件
+///
+/// 数据先写入同目录下的临时文件，`commit` 时 fsync 后重命名为目标文件，
+/// 因此中途崩溃或磁盘写满不会留下截断的目标文件，也不会破坏已有的旧版本。
+/// 未调用 `commit` 就被丢弃时删除临时文件
+pub struct AtomicFile {
+    file: Option<File>,
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    /// 在目标文件所在目录创建临时文件
+    pub fn create(path: &Path) -> Result<Self> {
+        let file_name = path
+            .file_name()
+            .context(format!("无效的输出路径: {}", path.display()))?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(
+            "{}{:08x}",
+            TEMP_FILE_MARKER,
+            rand::thread_rng().r#gen::<u32>()
+        ));
+        let temp_path = path.with_file_name(temp_name);
+
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .context(format!("无法创建临时文件: {}", temp_path.display()))?;
+
+        Ok(AtomicFile {
+            file: Some(file),
+            temp_path,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// 同步到磁盘并替换目标文件
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().expect("文件尚未提交");
+        file.sync_all()?;
+        drop(file);
+
+        // 文件已交出，Drop 不会再清理临时文件，重命名失败时在这里删除
+        if let Err(e) = fs::rename(&self.temp_path, &self.path) {
+            let _ = fs::remove_file(&self.temp_path);
+            return Err(e).context(format!("无法写入文件: {}", self.path.display()));
+        }
+        sync_parent_dir(&self.path);
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("文件尚未提交").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("文件尚未提交").flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// 原子地写入整个文件
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(data)?;
+    file.commit()
+}
+
+/// 同步目录项，确保重命名在断电后仍然有效（仅 Unix 支持打开目录）
+fn sync_parent_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// 使用 SIMD 加速计算文件 SHA256 哈希
+/// sha2 crate 会自动使用 CPU 的硬件加速（SHA-NI 指令集）
+pub fn compute_file_hash_simd(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    // 使用更大的缓冲区提高吞吐量
+    let mut buffer = vec![0u8; 64 * 1024]; // 64KB buffer
+
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        // sha2 会自动使用 SIMD 指令
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// 获取文件修改时间（本地时间，精确到纳秒）
+pub fn get_modified_time(path: &Path) -> Result<String> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?;
+    let datetime: chrono::DateTime<chrono::Local> = modified.into();
+    Ok(datetime.format("%Y-%m-%d %H:%M:%S%.9f").to_string())
+}
+
+/// 比较记录中的修改时间与当前修改时间
+///
+/// 旧版本记录的修改时间只精确到秒，此时只比较到秒
+pub fn same_modified_time(recorded: &str, current: &str) -> bool {
+    const SECONDS_LEN: usize = "YYYY-MM-DD HH:MM:SS".len();
+    recorded == current
+        || (recorded.len() == SECONDS_LEN
+            && current.len() > SECONDS_LEN
+            && current.starts_with(recorded))
+}
@@ -0,0 +1,421 @@
+//! 密钥派生与密钥文件
+//!
+//! 每次运行只从密码派生一次主密钥，KDF 参数和 salt 保存在输出目录的密钥文件中：
+//!
+//! ```text
+//! [MAGIC: 4字节 "ZKEY"]
+//! [VERSION: 1字节 = 1]
+//! [KDF_ID: 1字节]      1 = PBKDF2-HMAC-SHA256，2 = Argon2id
+//! [KDF_PARAMS_LEN: 1字节]
+//! [KDF_PARAMS: 变长]   PBKDF2: 迭代次数(u32 LE)；Argon2id: 内存KiB、迭代次数、并行度(各 u32 LE)
+//! [SALT_LEN: 1字节]
+//! [SALT: 16字节]
+//! [CHECK: 32字节]      主密钥派生出的校验值，用于识别密码错误
+//! ```
+//!
+//! 每个文件使用 HKDF-SHA256(主密钥, 文件 salt) 派生独立的子密钥。
+
+use crate::container::ContainerError;
+use crate::fsutil::write_atomic;
+use aes_gcm::{Aes256Gcm, Key};
+use anyhow::{Context, Result, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use hkdf::Hkdf;
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use sha2::Sha256;
+use std::{fmt, fs, path::Path};
+
+pub const KEY_FILE_NAME: &str = "xor.key";
+pub const KEY_MAGIC: &[u8; 4] = b"ZKEY";
+pub const KEY_VERSION: u8 = 1;
+pub const PBKDF2_ITERS: u32 = 100_000;
+pub const SALT_LEN: usize = 16;
+pub const CHECK_LEN: usize = 32;
+
+pub const KDF_PBKDF2: u8 = 1;
+pub const KDF_ARGON2ID: u8 = 2;
+
+/// Argon2id 默认参数：64MB 内存、3 次迭代、4 路并行
+pub const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+pub const ARGON2_ITERATIONS: u32 = 3;
+pub const ARGON2_PARALLELISM: u32 = 4;
+
+/// KDF 参数的取值范围。创建密钥时和读取文件头时都会检查，
+/// 防止写出无法使用的密钥文件，以及损坏或伪造的文件头使密钥派生耗尽内存或时间
+pub const PBKDF2_MIN_ITERATIONS: u32 = 1_000;
+pub const PBKDF2_MAX_ITERATIONS: u32 = 10_000_000;
+pub const ARGON2_MIN_MEMORY_KIB: u32 = 1024;
+/// 最大 4GB
+pub const ARGON2_MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+pub const ARGON2_MIN_ITERATIONS: u32 = 1;
+pub const ARGON2_MAX_ITERATIONS: u32 = 100;
+pub const ARGON2_MIN_PARALLELISM: u32 = 1;
+pub const ARGON2_MAX_PARALLELISM: u32 = 16;
+
+const FILE_KEY_INFO: &[u8] = b"hbsx file key v1";
+const CHECK_INFO: &[u8] = b"hbsx key check v1";
+
+/// 密码派生函数及其参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// PBKDF2-HMAC-SHA256
+    Pbkdf2 { iterations: u32 },
+    /// Argon2id
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::Argon2id {
+            memory_kib: ARGON2_MEMORY_KIB,
+            iterations: ARGON2_ITERATIONS,
+            parallelism: ARGON2_PARALLELISM,
+        }
+    }
+}
+
+impl fmt::Display for Kdf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kdf::Pbkdf2 { iterations } => write!(f, "PBKDF2-HMAC-SHA256 ({} 次迭代)", iterations),
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => write!(
+                f,
+                "Argon2id (内存 {} KiB, {} 次迭代, 并行度 {})",
+                memory_kib, iterations, parallelism
+            ),
+        }
+    }
+}
+
+impl Kdf {
+    /// 旧版本文件使用的固定参数
+    pub const LEGACY: Kdf = Kdf::Pbkdf2 {
+        iterations: PBKDF2_ITERS,
+    };
+
+    /// 检查参数是否在允许的范围内，返回错误原因
+    pub fn validate(&self) -> Result<(), String> {
+        let check = |name: &str, value: u32, min: u32, max: u32| {
+            if (min..=max).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!("{} 超出范围 {}..={}: {}", name, min, max, value))
+            }
+        };
+        match *self {
+            Kdf::Pbkdf2 { iterations } => check(
+                "PBKDF2 迭代次数",
+                iterations,
+                PBKDF2_MIN_ITERATIONS,
+                PBKDF2_MAX_ITERATIONS,
+            ),
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                check(
+                    "Argon2id 内存",
+                    memory_kib,
+                    ARGON2_MIN_MEMORY_KIB,
+                    ARGON2_MAX_MEMORY_KIB,
+                )?;
+                check(
+                    "Argon2id 迭代次数",
+                    iterations,
+                    ARGON2_MIN_ITERATIONS,
+                    ARGON2_MAX_ITERATIONS,
+                )?;
+                check(
+                    "Argon2id 并行度",
+                    parallelism,
+                    ARGON2_MIN_PARALLELISM,
+                    ARGON2_MAX_PARALLELISM,
+                )
+            }
+        }
+    }
+
+    /// 从密码派生 32 字节密钥，参数超出范围时返回错误
+    pub fn derive(&self, password: &str, salt: &[u8]) -> Result<Key<Aes256Gcm>> {
+        self.validate()
+            .map_err(|e| anyhow::anyhow!("KDF 参数无效: {}", e))?;
+        let mut key_bytes = [0u8; 32];
+        match *self {
+            Kdf::Pbkdf2 { iterations } => {
+                pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut key_bytes);
+            }
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = Params::new(memory_kib, iterations, parallelism, Some(32))
+                    .map_err(|e| anyhow::anyhow!("Argon2 参数无效: {}", e))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), salt, &mut key_bytes)
+                    .map_err(|e| anyhow::anyhow!("Argon2 密钥派生失败: {}", e))?;
+            }
+        }
+        Ok(key_bytes.into())
+    }
+
+    /// 编码为 `[KDF_ID][PARAMS_LEN][PARAMS]`
+    pub fn encode(&self) -> Vec<u8> {
+        let (id, params) = match *self {
+            Kdf::Pbkdf2 { iterations } => (KDF_PBKDF2, vec![iterations]),
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => (KDF_ARGON2ID, vec![memory_kib, iterations, parallelism]),
+        };
+        let params: Vec<u8> = params.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let mut data = vec![id, params.len() as u8];
+        data.extend_from_slice(&params);
+        data
+    }
+
+    /// 从 KDF_ID 和 PARAMS 解码，返回错误原因
+    pub fn decode(id: u8, params: &[u8]) -> Result<Kdf, String> {
+        let values: Vec<u32> = params
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        if !params.len().is_multiple_of(4) {
+            return Err("KDF 参数长度无效".to_string());
+        }
+
+        let kdf = match (id, values.as_slice()) {
+            (KDF_PBKDF2, &[iterations]) => Kdf::Pbkdf2 { iterations },
+            (KDF_ARGON2ID, &[memory_kib, iterations, parallelism]) => Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            },
+            (KDF_PBKDF2 | KDF_ARGON2ID, _) => return Err("KDF 参数无效".to_string()),
+            _ => return Err(format!("未知的 KDF: {}", id)),
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+}
+
+/// 运行级别的主密钥
+#[derive(Clone)]
+pub struct MasterKey {
+    key: [u8; 32],
+    kdf: Kdf,
+}
+
+impl MasterKey {
+    /// 读取输出目录中的密钥文件，不存在时使用 `kdf`（None 时为默认参数）生成新的密钥文件
+    ///
+    /// 已有密钥文件时沿用其中记录的 KDF 参数，`kdf` 与之不一致时返回错误
+    pub fn load_or_create(dir: &Path, password: &str, kdf: Option<Kdf>) -> Result<Self> {
+        check_kdf(dir, kdf)?;
+        match Self::load(dir, password)? {
+            Some(master) => Ok(master),
+            None => Self::create(dir, password, kdf.unwrap_or_default()),
+        }
+    }
+
+    /// 读取密钥文件并校验密码，密钥文件不存在时返回 None
+    pub fn load(dir: &Path, password: &str) -> Result<Option<Self>> {
+        let Some(key_file) = read_key_file(dir)? else {
+            return Ok(None);
+        };
+
+        let master = Self::derive(password, &key_file.salt, key_file.kdf)?;
+        if master.check_value() != key_file.check.as_slice() {
+            return Err(ContainerError::WrongPassword).context(format!(
+                "密码与密钥文件不匹配: {}",
+                dir.join(KEY_FILE_NAME).display()
+            ));
+        }
+
+        Ok(Some(master))
+    }
+
+    /// 生成新的 salt 并写入密钥文件
+    fn create(dir: &Path, password: &str, kdf: Kdf) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let master = Self::derive(password, &salt, kdf)?;
+
+        let mut data = KEY_MAGIC.to_vec();
+        data.push(KEY_VERSION);
+        data.extend_from_slice(&kdf.encode());
+        data.push(SALT_LEN as u8);
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&master.check_value());
+
+        fs::create_dir_all(dir)?;
+        write_atomic(&dir.join(KEY_FILE_NAME), &data)?;
+
+        Ok(master)
+    }
+
+    fn derive(password: &str, salt: &[u8], kdf: Kdf) -> Result<Self> {
+        Ok(MasterKey {
+            key: kdf.derive(password, salt)?.into(),
+            kdf,
+        })
+    }
+
+    /// 密钥文件中记录的 KDF
+    pub fn kdf(&self) -> Kdf {
+        self.kdf
+    }
+
+    /// 用于校验密码的值
+    fn check_value(&self) -> [u8; CHECK_LEN] {
+        key_check_value(&self.key)
+    }
+
+    /// 使用文件 salt 派生该文件的子密钥（HKDF-SHA256）
+    pub fn derive_file_key(&self, salt: &[u8]) -> Key<Aes256Gcm> {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(salt), &self.key)
+            .expand(FILE_KEY_INFO, &mut key)
+            .expect("HKDF 输出长度有效");
+        key.into()
+    }
+}
+
+/// 读取密钥文件中记录的 KDF（不需要密码），密钥文件不存在时返回 None
+pub fn stored_kdf(dir: &Path) -> Result<Option<Kdf>> {
+    Ok(read_key_file(dir)?.map(|key_file| key_file.kdf))
+}
+
+/// 检查指定的 KDF 是否与已有密钥文件一致（`kdf` 为 None 或没有密钥文件时总是通过）。
+/// 密钥文件中的参数创建后不能修改，忽略指定的参数会让用户误以为已经生效
+pub fn check_kdf(dir: &Path, kdf: Option<Kdf>) -> Result<()> {
+    if let (Some(kdf), Some(stored)) = (kdf, stored_kdf(dir)?)
+        && kdf != stored
+    {
+        bail!(
+            "密钥文件 {} 使用 {}，与指定的 {} 不一致；已有密钥文件时不能修改 KDF 参数，请去掉这些参数或使用新的输出目录",
+            dir.join(KEY_FILE_NAME).display(),
+            stored,
+            kdf
+        );
+    }
+    Ok(())
+}
+
+/// 解析后的密钥文件
+struct KeyFile {
+    kdf: Kdf,
+    salt: Vec<u8>,
+    check: Vec<u8>,
+}
+
+/// 读取并解析密钥文件，密钥文件不存在时返回 None
+fn read_key_file(dir: &Path) -> Result<Option<KeyFile>> {
+    let path = dir.join(KEY_FILE_NAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read(&path).context(format!("无法读取密钥文件: {}", path.display()))?;
+    let invalid = || format!("密钥文件格式无效: {}", path.display());
+    if data.len() < KEY_MAGIC.len() + 2 || &data[..KEY_MAGIC.len()] != KEY_MAGIC {
+        bail!(invalid());
+    }
+
+    if data[4] != KEY_VERSION {
+        bail!("不支持的密钥文件版本: {}", data[4]);
+    }
+
+    let (&id, rest) = data[5..].split_first().context(invalid())?;
+    let (&params_len, rest) = rest.split_first().context(invalid())?;
+    let (params, rest) = rest
+        .split_at_checked(params_len as usize)
+        .context(invalid())?;
+    let kdf =
+        Kdf::decode(id, params).map_err(|reason| anyhow::anyhow!("{}: {}", invalid(), reason))?;
+
+    let (&salt_len, rest) = rest.split_first().context(invalid())?;
+    let (salt, check) = rest
+        .split_at_checked(salt_len as usize)
+        .context(invalid())?;
+    if check.len() != CHECK_LEN {
+        bail!(invalid());
+    }
+
+    Ok(Some(KeyFile {
+        kdf,
+        salt: salt.to_vec(),
+        check: check.to_vec(),
+    }))
+}
+
+/// 由密钥派生的校验值，保存在密钥文件（主密钥）或容器头（从密码派生的文件密钥）中，
+/// 用于区分密码错误和数据被篡改
+pub fn key_check_value(key: &[u8]) -> [u8; CHECK_LEN] {
+    let mut check = [0u8; CHECK_LEN];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(CHECK_INFO, &mut check)
+        .expect("HKDF 输出长度有效");
+    check
+}
+
+/// 加密/解密使用的密钥材料
+///
+/// 有主密钥时新文件使用主密钥派生子密钥，否则每个文件使用 `kdf` 单独从密码派生；
+/// 解密时两种方式的文件都可以处理
+#[derive(Clone)]
+pub struct KeyRing {
+    password: String,
+    kdf: Kdf,
+    master: Option<MasterKey>,
+}
+
+impl KeyRing {
+    /// 只使用密码（每个文件使用默认 KDF 单独派生密钥）
+    pub fn from_password(password: &str) -> Self {
+        Self::with_kdf(password, Kdf::default())
+    }
+
+    /// 只使用密码，每个文件使用指定的 KDF 单独派生密钥
+    pub fn with_kdf(password: &str, kdf: Kdf) -> Self {
+        KeyRing {
+            password: password.to_string(),
+            kdf,
+            master: None,
+        }
+    }
+
+    /// 使用主密钥，同时保留密码以读取旧文件
+    pub fn with_master_key(password: &str, master: MasterKey) -> Self {
+        KeyRing {
+            password: password.to_string(),
+            kdf: master.kdf(),
+            master: Some(master),
+        }
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    /// 按文件从密码派生密钥时使用的 KDF
+    pub fn kdf(&self) -> Kdf {
+        self.kdf
+    }
+
+    pub fn master_key(&self) -> Option<&MasterKey> {
+        self.master.as_ref()
+    }
+}
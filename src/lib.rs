@@ -1 +1,23 @@
+pub mod container;
 pub mod db;
+pub mod engine;
+pub mod fsutil;
+pub mod keys;
+pub mod manifest;
+pub mod metadata;
+pub mod migrations;
+pub mod names;
+pub mod pathenc;
+pub mod walk;
+
+pub use engine::{
+    EncryptOptions, EncryptReport, FileEvent, FileStatus, FlushPolicy, Plan, PlannedAction,
+    RestoreOptions, RestoreReport, VerifyMode, VerifyOptions, VerifyReport, encrypt_tree,
+    plan_tree, restore_tree, verify_tree,
+};
+pub use walk::FileFilter;
+
+/// README 中的 Rust 示例作为文档测试编译，避免示例与接口脱节
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+struct ReadmeDoctests;
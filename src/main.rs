@@ -1,148 +1,269 @@
-use aes_gcm::{
-    Aes256Gcm, Key, Nonce,
-    aead::{Aead, KeyInit},
+use anyhow::{Result, bail};
+use clap::Parser;
+use hbsx::db::{CATALOG_FILE_NAME, Database, EntryKind};
+use hbsx::engine::{
+    EncryptOptions, FileEvent, FileStatus, PlannedAction, RestoreFailureKind, RestoreOptions,
+    VerifyMode, VerifyOptions, encrypt_tree, plan_tree, restore_tree, verify_tree,
 };
-use anyhow::{Context, Result};
-use csv::Writer;
-use pbkdf2::pbkdf2_hmac;
-use rand::RngCore;
-use rayon::prelude::*;
-use sha2::{Digest, Sha256};
-use std::{
-    fs::{self, File},
-    io::{Read, Write},
-    path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+use hbsx::keys::stored_kdf;
+use hbsx::manifest::write_run_report;
+use hbsx::names::uses_encrypted_names;
+use hbsx::pathenc::decode_path;
+
+mod cli;
+mod password;
+use cli::{Cli, Command, EncryptArgs, LogArgs, PruneArgs, RestoreArgs, VerifyArgs};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
 };
-use walkdir::WalkDir;
-use zstd::stream::Encoder;
-
-mod db;
-use db::{Database, FileRecord, LogRecord};
-
-const MAGIC: &[u8; 4] = b"ZENC";
-const VERSION: u8 = 1;
-const PBKDF2_ITERS: u32 = 100_000;
-const SALT_LEN: usize = 16;
-const NONCE_LEN: usize = 12;
-const ZSTD_WORKERS: u32 = 4; // Zstd 内部线程数
-
-fn main() -> Result<()> {
-    let input_dir = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "./input".to_string());
-    let output_dir = std::env::args()
-        .nth(2)
-        .unwrap_or_else(|| "./output".to_string());
-    let password = std::env::args()
-        .nth(3)
-        .unwrap_or_else(|| "default_password".to_string());
-
-    println!("📁 输入目录: {}", input_dir);
-    println!("📁 输出目录: {}", output_dir);
-    println!("🔐 密码已设置");
-    println!("💾 数据库位置: {}", Database::get_db_path_string()?);
-    println!("🚀 使用 Rayon 多线程 + Zstd 多线程压缩 + SIMD 加速哈希\n");
 
-    let input_path = Path::new(&input_dir);
-    let output_path = Path::new(&output_dir);
-
-    // 创建输出目录
-    fs::create_dir_all(output_path)?;
-
-    // 初始化数据库
-    let db = Arc::new(Mutex::new(Database::new()?));
-
-    // 收集所有文件路径
-    let file_paths: Vec<PathBuf> = WalkDir::new(input_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .map(|e| e.path().to_path_buf())
-        .collect();
-
-    let total_files = file_paths.len();
-    println!("📊 找到 {} 个文件\n", total_files);
-
-    let password = Arc::new(password);
-    let input_path = Arc::new(input_path.to_path_buf());
-    let output_path = Arc::new(output_path.to_path_buf());
-
-    // 用于批量收集需要写入数据库的记录和日志
-    let pending_records = Arc::new(Mutex::new(Vec::new()));
-    let pending_logs = Arc::new(Mutex::new(Vec::new()));
-
-    // 使用 Rayon 并行处理文件
-    let results: Vec<(FileRecord, String)> = file_paths
-        .par_iter()
-        .filter_map(|file_path| {
-            match process_file_with_check(
-                file_path,
-                &input_path,
-                &output_path,
-                &password,
-                &db,
-                &pending_records,
-                &pending_logs,
-            ) {
-                Ok(Some((record, status))) => {
-                    println!("{} {}", status, record.relative_path);
-                    Some((record, status))
-                }
-                Ok(None) => None,
-                Err(e) => {
-                    let error_msg = format!("❌ 错误处理 {:?}: {}", file_path, e);
-                    eprintln!("{}", error_msg);
-
-                    // 记录错误日志到批量队列
-                    if let Ok(relative_path) = file_path.strip_prefix(&*input_path) {
-                        let log = LogRecord {
-                            file_path: relative_path.to_string_lossy().to_string(),
-                            action: "process".to_string(),
-                            status: "error".to_string(),
-                            message: e.to_string(),
-                            timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-                        };
-                        pending_logs.lock().unwrap().push(log);
-                    }
-
-                    None
-                }
-            }
-        })
-        .collect();
+/// 退出码：
+/// - 0：成功
+/// - 1：运行出错（参数错误、密码错误、数据库错误等）
+/// - 2：部分文件处理失败
+/// - 3：所有需要处理的文件都失败
+/// - 4：校验失败
+/// - 130：被 Ctrl-C 中断
+const EXIT_PARTIAL_FAILURE: u8 = 2;
+const EXIT_TOTAL_FAILURE: u8 = 3;
+const EXIT_VERIFY_FAILURE: u8 = 4;
+const EXIT_INTERRUPTED: u8 = 130;
+
+fn main() -> Result<ExitCode> {
+    // clap 的参数错误默认以 2 退出，与部分失败冲突，统一改为 1
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            err.print()?;
+            return Ok(if err.use_stderr() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            });
+        }
+    };
+    let db = cli.db.as_deref();
+
+    match cli.command {
+        Command::Encrypt(args) => encrypt(db, *args),
+        Command::Restore(args) => restore(db, args),
+        Command::Verify(args) => verify(db, args),
+        Command::Status => status(db).map(|_| ExitCode::SUCCESS),
+        Command::Log(args) => show_logs(db, args).map(|_| ExitCode::SUCCESS),
+        Command::Prune(args) => prune(db, args).map(|_| ExitCode::SUCCESS),
+    }
+}
 
-    // 批量写入数据库
-    println!("\n💾 正在批量写入数据库...");
-    let records_to_write = pending_records.lock().unwrap();
-    let logs_to_write = pending_logs.lock().unwrap();
+/// 根据成功和失败的文件数确定退出码
+fn failure_exit_code(succeeded: usize, failed: usize) -> ExitCode {
+    match (succeeded, failed) {
+        (_, 0) => ExitCode::SUCCESS,
+        (0, _) => ExitCode::from(EXIT_TOTAL_FAILURE),
+        _ => ExitCode::from(EXIT_PARTIAL_FAILURE),
+    }
+}
+
+/// 运行结束时列出失败的文件及原因
+fn print_failures<'a>(title: &str, failures: impl ExactSizeIterator<Item = (String, &'a str)>) {
+    if failures.len() == 0 {
+        return;
+    }
+    eprintln!("\n❌ {} {} 个文件:", title, failures.len());
+    for (path, reason) in failures {
+        eprintln!("   {}: {}", path, reason);
+    }
+}
+
+/// 打开数据库，按以下优先级确定位置：
+/// 1. `create_catalog` 为 true 时使用 `catalog_dir` 中的目录数据库
+/// 2. `--db` 参数或 XOR_DB 环境变量
+/// 3. `catalog_dir` 中已存在的目录数据库
+/// 4. 默认位置 ~/.xor/data.db
+///
+/// 目录数据库以明文保存路径，`encrypt_names` 为 true 时不使用
+fn open_database(
+    db: Option<&Path>,
+    catalog_dir: Option<&Path>,
+    create_catalog: bool,
+    encrypt_names: bool,
+) -> Result<Database> {
+    let path = database_path(db, catalog_dir, create_catalog, encrypt_names)?;
+    Database::open(&path)
+}
 
-    if !records_to_write.is_empty() {
-        db.lock().unwrap().batch_upsert_files(&records_to_write)?;
-        println!("✅ 已写入 {} 条文件记录", records_to_write.len());
+/// 选择要使用的数据库文件：`--db`、输出目录中的目录数据库，或默认位置
+fn database_path(
+    db: Option<&Path>,
+    catalog_dir: Option<&Path>,
+    create_catalog: bool,
+    encrypt_names: bool,
+) -> Result<PathBuf> {
+    let catalog = catalog_dir.map(|dir| dir.join(CATALOG_FILE_NAME));
+    if encrypt_names
+        && let Some(catalog) = &catalog
+        && (create_catalog || (db.is_none() && catalog.is_file()))
+    {
+        bail!(
+            "加密文件名时不能使用输出目录中的目录数据库 {}，请移走该文件或使用 --db 指定数据库",
+            catalog.display()
+        );
+    }
+    match (db, catalog) {
+        (_, Some(catalog)) if create_catalog => Ok(catalog),
+        (Some(path), _) => Ok(path.to_path_buf()),
+        (None, Some(catalog)) if catalog.is_file() => Ok(catalog),
+        _ => Database::get_db_path(),
+    }
+}
+
+/// 数据库位置（用于显示）
+fn db_location(db: &Database) -> String {
+    db.path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "(内存)".to_string())
+}
+
+/// 第一次 Ctrl-C 设置取消标记，等待正在处理的文件完成并写入数据库；
+/// 第二次 Ctrl-C 立即退出
+fn install_interrupt_handler() -> Result<Arc<AtomicBool>> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = cancel.clone();
+    ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+        eprintln!("\n⏹️ 收到中断信号，正在完成当前文件并保存进度（再次按 Ctrl-C 立即退出）");
+    })?;
+    Ok(cancel)
+}
+
+/// 加密模式：压缩并加密输入目录（增量处理）
+fn encrypt(db: Option<&Path>, args: EncryptArgs) -> Result<ExitCode> {
+    if args.dry_run {
+        return plan(db, args);
+    }
+
+    let kdf = args.kdf.kdf(stored_kdf(&args.output)?)?;
+    let password = password::read_password(&args.password, true)?;
+    let options = EncryptOptions {
+        flush: args.flush(),
+        filter: args.filter.filter(),
+        input: args.input,
+        output: args.output,
+        password,
+        kdf,
+        orphans: args.orphans.into(),
+        symlinks: args.symlinks.into(),
+        encrypt_names: args.encrypt_names,
+        cancel: Some(install_interrupt_handler()?),
+        manifest_formats: args.manifest_format.iter().map(|&f| f.into()).collect(),
+        delta_manifest: args.delta_manifest,
+    };
+    let mut db = open_database(
+        db,
+        Some(&options.output),
+        args.catalog_in_output,
+        options.encrypt_names || uses_encrypted_names(&options.output),
+    )?;
+
+    println!("📁 输入目录: {}", options.input.display());
+    println!("📁 输出目录: {}", options.output.display());
+    println!("🔐 密码已设置");
+    println!("💾 数据库位置: {}", db_location(&db));
+    println!("🚀 使用 Rayon 多线程 + Zstd 多线程压缩 + SIMD 加速哈希\n");
+
+    let report = encrypt_tree(&mut db, &options, |event| match event {
+        FileEvent::Processed(file) => {
+            let status = match file.status {
+                FileStatus::New => "✅ 新增:",
+                FileStatus::Updated => "🔄 更新:",
+            };
+            println!("{} {}", status, file.record.relative_path);
+        }
+        FileEvent::Failed(file) => {
+            eprintln!("❌ 错误处理 {:?}: {}", file.path, file.error);
+        }
+        FileEvent::Deleted(file) => match &file.output {
+            Some(output) => println!(
+                "🗑️ 已删除: {} (输出: {})",
+                file.relative_path,
+                output.display()
+            ),
+            None => println!("🗑️ 已删除: {}", file.relative_path),
+        },
+    })?;
+
+    if let Some(path) = &args.report {
+        write_run_report(path, &report.summary)?;
     }
 
-    if !logs_to_write.is_empty() {
-        db.lock().unwrap().batch_add_logs(&logs_to_write)?;
-        println!("✅ 已写入 {} 条日志记录", logs_to_write.len());
+    if report.interrupted {
+        eprintln!(
+            "\n⏹️ 已中断：{} 个文件已处理并写入数据库，再次运行将从中断处继续",
+            report.processed.len()
+        );
+        return Ok(ExitCode::from(EXIT_INTERRUPTED));
+    }
+    if !report.walk_complete {
+        eprintln!("⚠️ 部分目录无法读取，已跳过删除检测");
     }
 
-    // 生成 CSV 清单（兼容性保留）
-    let records: Vec<FileRecord> = results.iter().map(|(r, _)| r.clone()).collect();
-    let manifest_path = output_path.join("manifest.csv");
-    write_manifest(&manifest_path, &records)?;
+    println!("\n🔑 密钥派生: {}", report.kdf);
+    println!("📊 找到 {} 个文件", report.total_files);
+    if report.summary.symlinks > 0 {
+        println!("🔗 已记录 {} 个符号链接", report.summary.symlinks);
+    }
+    if report.summary.empty_dirs > 0 {
+        println!("📂 已记录 {} 个空目录", report.summary.empty_dirs);
+    }
+    for entry in &report.skipped {
+        println!("⏭️ 已跳过: {} ({})", entry.path.display(), entry.reason);
+    }
+    println!("💾 已写入数据库");
+    if !report.processed.is_empty() {
+        println!("✅ 已写入 {} 条文件记录", report.processed.len());
+    }
+    if !report.deleted.is_empty() {
+        println!("🗑️ 已标记 {} 个删除的文件", report.deleted.len());
+    }
+    if report.logs_written > 0 {
+        println!("✅ 已写入 {} 条日志记录", report.logs_written);
+    }
 
     // 计算统计信息
-    let total_original_size: u64 = records.iter().map(|r| r.original_size).sum();
-    let total_output_size: u64 = records.iter().map(|r| r.output_size).sum();
+    let total_original_size = report.summary.original_bytes;
+    let total_output_size = report.summary.output_bytes;
     let compression_ratio = if total_original_size > 0 {
         (total_output_size as f64 / total_original_size as f64) * 100.0
     } else {
         0.0
     };
 
-    println!("\n📋 清单已生成: {}", manifest_path.display());
-    println!("🎉 所有文件处理完成！共 {} 个文件", records.len());
+    println!();
+    if report.encrypt_names {
+        println!("🙈 文件名已加密，原始路径保存在加密索引中（不生成明文清单）");
+    }
+    for path in &report.manifest_paths {
+        println!("📋 清单已生成: {}", path.display());
+    }
+    for path in &report.delta_manifest_paths {
+        println!("📋 变更清单已生成: {}", path.display());
+    }
+    if let Some(path) = &args.report {
+        println!("📋 运行报告已生成: {}", path.display());
+    }
+    if report.failed.is_empty() {
+        println!("🎉 所有文件处理完成！共 {} 个文件", report.processed.len());
+    } else {
+        println!(
+            "⚠️ 处理完成：成功 {} 个文件，失败 {} 个文件",
+            report.processed.len(),
+            report.failed.len()
+        );
+    }
     println!("📊 统计信息:");
     println!(
         "   原始总大小: {} ({} MB)",
@@ -160,325 +281,283 @@ fn main() -> Result<()> {
         format_size(total_original_size.saturating_sub(total_output_size)),
         total_original_size.saturating_sub(total_output_size) / 1024 / 1024
     );
-
-    Ok(())
+    println!("   耗时: {:.1} 秒", report.summary.duration_secs);
+
+    print_failures(
+        "处理失败",
+        report
+            .failed
+            .iter()
+            .map(|f| (f.path.display().to_string(), f.error.as_str())),
+    );
+    Ok(failure_exit_code(
+        report.processed.len(),
+        report.failed.len(),
+    ))
 }
 
-/// 格式化文件大小
-fn format_size(size: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-
-    if size >= GB {
-        format!("{:.2} GB", size as f64 / GB as f64)
-    } else if size >= MB {
-        format!("{:.2} MB", size as f64 / MB as f64)
-    } else if size >= KB {
-        format!("{:.2} KB", size as f64 / KB as f64)
-    } else {
-        format!("{} B", size)
+/// 预演模式：显示增量加密会执行的操作，不需要密码
+fn plan(db: Option<&Path>, args: EncryptArgs) -> Result<ExitCode> {
+    let kdf = args.kdf.kdf(stored_kdf(&args.output)?)?;
+    let options = EncryptOptions {
+        flush: args.flush(),
+        filter: args.filter.filter(),
+        input: args.input,
+        output: args.output,
+        password: String::new(),
+        kdf,
+        orphans: args.orphans.into(),
+        symlinks: args.symlinks.into(),
+        encrypt_names: args.encrypt_names,
+        cancel: None,
+        manifest_formats: Vec::new(),
+        delta_manifest: false,
+    };
+    // 预演以只读方式打开数据库，不创建数据库文件也不执行迁移
+    let db_path = database_path(
+        db,
+        Some(&options.output),
+        false,
+        options.encrypt_names || uses_encrypted_names(&options.output),
+    )?;
+    let db = Database::open_read_only(&db_path)?;
+    let plan = plan_tree(&db, &options)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        return Ok(ExitCode::SUCCESS);
     }
-}
 
-/// 检查并处理文件（增量处理逻辑）
-fn process_file_with_check(
-    file_path: &Path,
-    input_path: &Path,
-    output_path: &Path,
-    password: &str,
-    db: &Arc<Mutex<Database>>,
-    pending_records: &Arc<Mutex<Vec<FileRecord>>>,
-    pending_logs: &Arc<Mutex<Vec<LogRecord>>>,
-) -> Result<Option<(FileRecord, String)>> {
-    let relative_path = file_path
-        .strip_prefix(input_path)?
-        .to_str()
-        .context("路径转换失败")?
-        .to_string();
-
-    // 获取当前文件的修改时间
-    let current_modified_time = get_modified_time(file_path)?;
-
-    // 检查数据库中是否存在该文件
-    let existing_record = db.lock().unwrap().file_exists(&relative_path)?;
-
-    let should_process = if let Some(existing) = &existing_record {
-        // 文件存在于数据库中，检查是否需要更新
-        if existing.modified_time != current_modified_time {
-            // 修改时间不同，进一步检查 hash
-            let current_hash = compute_file_hash_simd(file_path)?;
-
-            if existing.original_hash != current_hash {
-                // Hash 不同，需要重新处理
-                queue_log(
-                    pending_logs,
-                    &relative_path,
-                    "check",
-                    "changed",
-                    "文件已变化 (修改时间和哈希均不同)",
-                );
-                true
-            } else {
-                // Hash 相同但修改时间不同（可能只是 touch 了文件）
-                queue_log(
-                    pending_logs,
-                    &relative_path,
-                    "check",
-                    "skip",
-                    "文件未实际变化 (仅修改时间变化)",
-                );
-                false
-            }
-        } else {
-            // 修改时间相同，跳过处理
-            false
+    println!("📁 输入目录: {}", options.input.display());
+    println!("📁 输出目录: {}", options.output.display());
+    println!("💾 数据库位置: {}", db_path.display());
+    println!("🔍 预演模式：不写入输出文件和数据库\n");
+
+    for file in &plan.files {
+        match file.action {
+            PlannedAction::Unchanged | PlannedAction::Touched | PlannedAction::Filtered => {}
+            action => println!(
+                "{} {} ({})",
+                action.label(),
+                file.relative_path,
+                format_size(file.size)
+            ),
         }
-    } else {
-        // 数据库中不存在，需要处理
-        queue_log(pending_logs, &relative_path, "check", "new", "新文件");
-        true
-    };
+    }
+    if !plan.walk_complete {
+        eprintln!("⚠️ 部分目录无法读取，将跳过删除检测");
+    }
 
-    if !should_process {
-        return Ok(None);
+    println!("\n📊 找到 {} 个文件", plan.total_files);
+    for (action, count) in &plan.counts {
+        println!("   {}: {} 个文件", action.label(), count);
     }
+    println!("   需要处理: {}", format_size(plan.bytes_to_process));
 
-    // 执行实际的处理
-    match process_file(file_path, input_path, output_path, password) {
-        Ok(record) => {
-            // 添加到批量写入队列
-            pending_records.lock().unwrap().push(record.clone());
+    Ok(ExitCode::SUCCESS)
+}
 
-            // 记录成功日志到队列
-            queue_log(
-                pending_logs,
-                &relative_path,
-                "process",
-                "success",
-                "文件处理成功",
-            );
+/// 还原模式：将加密目录还原为原始目录结构
+fn restore(db: Option<&Path>, args: RestoreArgs) -> Result<ExitCode> {
+    let password = password::read_password(&args.password, false)?;
+    let options = RestoreOptions {
+        input: args.input,
+        output: args.output,
+        password,
+    };
+    let db = open_database(db, Some(&options.input), false, false)?;
 
-            let status = if existing_record.is_some() {
-                "🔄 更新:"
-            } else {
-                "✅ 新增:"
-            };
+    println!("📁 加密目录: {}", options.input.display());
+    println!("📁 还原目录: {}", options.output.display());
+    println!("💾 数据库位置: {}", db_location(&db));
+
+    let report = restore_tree(&db, &options)?;
 
-            Ok(Some((record, status.to_string())))
+    println!(
+        "📊 找到 {} 个加密文件\n",
+        report.restored.len() + report.failed.len()
+    );
+
+    for file in &report.restored {
+        println!("✅ 已还原: {}", file.relative_path);
+    }
+    for entry in &report.entries {
+        match entry.kind {
+            EntryKind::Dir => println!("📂 已创建空目录: {}", entry.relative_path),
+            EntryKind::Symlink => println!(
+                "🔗 已还原符号链接: {} -> {}",
+                entry.relative_path,
+                entry.target.as_deref().unwrap_or_default()
+            ),
         }
-        Err(e) => {
-            // 记录失败日志到队列
-            queue_log(
-                pending_logs,
-                &relative_path,
-                "process",
-                "failed",
-                &e.to_string(),
-            );
-            Err(e)
+    }
+
+    let mut wrong_password = 0;
+    let mut corrupted = 0;
+    for failure in &report.failed {
+        match failure.kind {
+            RestoreFailureKind::WrongPassword => {
+                eprintln!("🔑 密码错误: {}", failure.path.display());
+                wrong_password += 1;
+            }
+            RestoreFailureKind::Corrupted => {
+                eprintln!(
+                    "💥 文件损坏: {} ({})",
+                    failure.path.display(),
+                    failure.error
+                );
+                corrupted += 1;
+            }
+            RestoreFailureKind::Other => {
+                eprintln!("❌ 错误处理 {:?}: {}", failure.path, failure.error);
+                corrupted += 1;
+            }
         }
     }
+
+    println!("\n🎉 还原完成！成功 {} 个文件", report.restored.len());
+    if wrong_password > 0 {
+        println!("🔑 密码错误: {} 个文件", wrong_password);
+    }
+    if corrupted > 0 {
+        println!("💥 文件损坏或无法读取: {} 个文件", corrupted);
+    }
+
+    print_failures(
+        "还原失败",
+        report
+            .failed
+            .iter()
+            .map(|f| (f.path.display().to_string(), f.error.as_str())),
+    );
+    Ok(failure_exit_code(
+        report.restored.len() + report.entries.len(),
+        report.failed.len(),
+    ))
 }
 
-/// 将日志添加到队列（用于批量写入）
-fn queue_log(
-    pending_logs: &Arc<Mutex<Vec<LogRecord>>>,
-    file_path: &str,
-    action: &str,
-    status: &str,
-    message: &str,
-) {
-    let log = LogRecord {
-        file_path: file_path.to_string(),
-        action: action.to_string(),
-        status: status.to_string(),
-        message: message.to_string(),
-        timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+/// 校验模式：重新计算输出文件哈希并与数据库记录比对
+fn verify(db: Option<&Path>, args: VerifyArgs) -> Result<ExitCode> {
+    let mode = if args.deep {
+        VerifyMode::Deep {
+            password: password::read_password(&args.password, false)?,
+        }
+    } else {
+        VerifyMode::Fast
     };
-    pending_logs.lock().unwrap().push(log);
-}
+    let mut db = open_database(db, Some(&args.output), false, false)?;
+
+    println!("📁 加密目录: {}", args.output.display());
+    println!("💾 数据库位置: {}", db_location(&db));
+    match mode {
+        VerifyMode::Fast => println!("⚡ 快速校验：比对输出文件哈希"),
+        VerifyMode::Deep { .. } => println!("🔍 深度校验：解密并比对原始文件哈希"),
+    }
 
-/// 记录日志到数据库（立即写入，用于旧代码兼容）
-#[allow(dead_code)]
-fn log_action(
-    db: &Arc<Mutex<Database>>,
-    file_path: &str,
-    action: &str,
-    status: &str,
-    message: &str,
-) -> Result<()> {
-    let log = LogRecord {
-        file_path: file_path.to_string(),
-        action: action.to_string(),
-        status: status.to_string(),
-        message: message.to_string(),
-        timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    let options = VerifyOptions {
+        output: args.output,
+        mode,
     };
-    db.lock().unwrap().add_log(&log)?;
-    Ok(())
-}
-fn process_file(
-    file_path: &Path,
-    input_path: &Path,
-    output_path: &Path,
-    password: &str,
-) -> Result<FileRecord> {
-    let relative_path = file_path
-        .strip_prefix(input_path)?
-        .to_str()
-        .context("路径转换失败")?
-        .to_string();
-
-    // 获取原始文件大小
-    let original_size = fs::metadata(file_path)?.len();
-
-    // 并行计算原始文件 hash（使用 SIMD 加速）
-    let original_hash = compute_file_hash_simd(file_path)?;
-
-    // 获取修改时间
-    let modified_time = get_modified_time(file_path)?;
-
-    // 压缩 + 加密
-    let output_file_path = output_path.join(&relative_path).with_extension("zstd.enc");
-
-    // 确保输出文件的父目录存在
-    if let Some(parent) = output_file_path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
-    compress_and_encrypt_mt(file_path, &output_file_path, password)?;
-
-    // 获取输出文件大小
-    let output_size = fs::metadata(&output_file_path)?.len();
-
-    // 计算输出文件 hash
-    let output_hash = compute_file_hash_simd(&output_file_path)?;
-
-    Ok(FileRecord {
-        id: None,
-        relative_path,
-        modified_time,
-        original_hash,
-        output_hash,
-        original_size,
-        output_size,
-        created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-    })
+    let report = verify_tree(&mut db, &options)?;
+    println!("📊 数据库中共 {} 条文件记录\n", report.checked);
+
+    if !report.mismatched.is_empty() {
+        print_failures(
+            "校验失败",
+            report
+                .mismatched
+                .iter()
+                .map(|m| (m.relative_path.clone(), m.reason.as_str())),
+        );
+        return Ok(ExitCode::from(EXIT_VERIFY_FAILURE));
+    }
+
+    println!("✅ 所有 {} 个文件校验通过", report.checked);
+    Ok(ExitCode::SUCCESS)
 }
 
-/// 多线程压缩并加密文件
-fn compress_and_encrypt_mt(input: &Path, output: &Path, password: &str) -> Result<()> {
-    // 1. 读取原始文件
-    let mut input_file = File::open(input)?;
-    let mut original_data = Vec::new();
-    input_file.read_to_end(&mut original_data)?;
-
-    // 2. Zstd 多线程压缩
-    let mut encoder = Encoder::new(Vec::new(), 3)?;
-
-    // 启用 Zstd 多线程压缩（需要 zstdmt feature）
-    encoder.multithread(ZSTD_WORKERS)?;
-
-    encoder.write_all(&original_data)?;
-    let compressed = encoder.finish()?;
-
-    // 3. 生成随机 salt 和 nonce
-    let mut salt = [0u8; SALT_LEN];
-    let mut nonce_bytes = [0u8; NONCE_LEN];
-    rand::thread_rng().fill_bytes(&mut salt);
-    rand::thread_rng().fill_bytes(&mut nonce_bytes);
-
-    // 4. 从密码派生密钥（PBKDF2-HMAC-SHA256）
-    let mut key_bytes = [0u8; 32];
-    pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, PBKDF2_ITERS, &mut key_bytes);
-
-    // 5. AES-256-GCM 加密
-    let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(key);
-    let nonce = Nonce::from_slice(&nonce_bytes);
-
-    let ciphertext = cipher
-        .encrypt(nonce, compressed.as_ref())
-        .map_err(|e| anyhow::anyhow!("加密失败: {:?}", e))?;
-
-    // 6. 写入自定义容器格式
-    let mut output_file = File::create(output)?;
-    output_file.write_all(MAGIC)?;
-    output_file.write_all(&[VERSION])?;
-    output_file.write_all(&[SALT_LEN as u8])?;
-    output_file.write_all(&salt)?;
-    output_file.write_all(&[NONCE_LEN as u8])?;
-    output_file.write_all(&nonce_bytes)?;
-    output_file.write_all(&ciphertext)?;
+/// 显示数据库统计信息
+fn status(db: Option<&Path>) -> Result<()> {
+    let db = open_database(db, None, false, false)?;
+    let sources = db.get_sources()?;
+
+    println!("💾 数据库位置: {}", db_location(&db));
+    println!("📂 来源: {} 个", sources.len());
+
+    for source in &sources {
+        let records = db.get_all_files(source.id)?;
+        let total_original_size: u64 = records.iter().map(|r| r.original_size).sum();
+        let total_output_size: u64 = records.iter().map(|r| r.output_size).sum();
+
+        println!(
+            "\n📁 {} → {}",
+            decode_path(&source.input_root).display(),
+            decode_path(&source.output_root).display()
+        );
+        println!("📊 文件记录: {} 个", records.len());
+        let deleted = db.get_deleted_files(source.id)?.len();
+        if deleted > 0 {
+            println!("🗑️ 已删除记录: {} 个", deleted);
+        }
+        println!("   原始总大小: {}", format_size(total_original_size));
+        println!("   输出总大小: {}", format_size(total_output_size));
+    }
+
+    let unsourced = db.count_unsourced_files()?;
+    if unsourced > 0 {
+        println!("\n⚠️ 未关联来源的旧记录: {} 个", unsourced);
+    }
+
+    if let Some(last) = db.get_recent_logs(1)?.first() {
+        println!("\n🕒 最近一次操作: {}", last.timestamp);
+    }
 
     Ok(())
 }
 
-/// 使用 SIMD 加速计算文件 SHA256 哈希
-/// sha2 crate 会自动使用 CPU 的硬件加速（SHA-NI 指令集）
-fn compute_file_hash_simd(path: &Path) -> Result<String> {
-    let mut file = File::open(path)?;
-    let mut hasher = Sha256::new();
+/// 显示最近的处理日志
+fn show_logs(db: Option<&Path>, args: LogArgs) -> Result<()> {
+    let logs = open_database(db, None, false, false)?
+        .get_recent_logs_by_status(args.limit, args.status.as_deref())?;
 
-    // 使用更大的缓冲区提高吞吐量
-    let mut buffer = vec![0u8; 64 * 1024]; // 64KB buffer
+    for log in &logs {
+        println!(
+            "{} [{}] {} {}: {}",
+            log.timestamp, log.action, log.status, log.file_path, log.message
+        );
+    }
 
-    loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
-        }
-        // sha2 会自动使用 SIMD 指令
-        hasher.update(&buffer[..bytes_read]);
+    if logs.is_empty() {
+        println!("暂无日志");
     }
 
-    Ok(format!("{:x}", hasher.finalize()))
+    Ok(())
 }
 
-/// 获取文件修改时间
-fn get_modified_time(path: &Path) -> Result<String> {
-    let metadata = fs::metadata(path)?;
-    let modified = metadata.modified()?;
-    let datetime: chrono::DateTime<chrono::Local> = modified.into();
-    Ok(datetime.format("%Y-%m-%d %H:%M:%S").to_string())
-}
+/// 清理旧日志
+fn prune(db: Option<&Path>, args: PruneArgs) -> Result<()> {
+    let cutoff = chrono::Local::now() - chrono::Duration::days(args.older_than_days.into());
+    let cutoff = cutoff.format("%Y-%m-%d %H:%M:%S").to_string();
+
+    let deleted = open_database(db, None, false, false)?.prune_logs(&cutoff)?;
+    println!("🧹 已删除 {} 条早于 {} 的日志", deleted, cutoff);
 
-/// 写入 CSV 清单（线程安全）
-fn write_manifest(path: &Path, records: &[FileRecord]) -> Result<()> {
-    let mut writer = Writer::from_path(path)?;
-
-    writer.write_record([
-        "文件路径",
-        "最后修改时间",
-        "原始文件哈希",
-        "输出文件哈希",
-        "原始大小(字节)",
-        "输出大小(字节)",
-        "压缩率",
-    ])?;
-
-    for record in records {
-        let compression_ratio = if record.original_size > 0 {
-            format!(
-                "{:.2}%",
-                (record.output_size as f64 / record.original_size as f64) * 100.0
-            )
-        } else {
-            "N/A".to_string()
-        };
-
-        writer.write_record([
-            &record.relative_path,
-            &record.modified_time,
-            &record.original_hash,
-            &record.output_hash,
-            &record.original_size.to_string(),
-            &record.output_size.to_string(),
-            &compression_ratio,
-        ])?;
-    }
-
-    writer.flush()?;
     Ok(())
 }
+
+/// 格式化文件大小
+fn format_size(size: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+
+    if size >= GB {
+        format!("{:.2} GB", size as f64 / GB as f64)
+    } else if size >= MB {
+        format!("{:.2} MB", size as f64 / MB as f64)
+    } else if size >= KB {
+        format!("{:.2} KB", size as f64 / KB as f64)
+    } else {
+        format!("{} B", size)
+    }
+}
@@ -0,0 +1,193 @@
+//! 输出目录中的清单文件
+//!
+//! - CSV：中文表头，便于直接打开查看
+//! - JSON：`{"summary": {...}, "files": [...]}`
+//! - NDJSON：每行一个对象，`type` 为 `file` 的文件记录，最后一行为 `type` 为 `summary` 的运行摘要
+
+use crate::db::FileRecord;
+use crate::engine::RunSummary;
+use crate::fsutil::AtomicFile;
+use anyhow::Result;
+use csv::Writer;
+use serde::Serialize;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const HEADER: [&str; 7] = [
+    "文件路径",
+    "最后修改时间",
+    "原始文件哈希",
+    "输出文件哈希",
+    "原始大小(字节)",
+    "输出大小(字节)",
+    "压缩率",
+];
+
+/// 清单格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl ManifestFormat {
+    pub const ALL: [ManifestFormat; 3] = [
+        ManifestFormat::Csv,
+        ManifestFormat::Json,
+        ManifestFormat::Ndjson,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ManifestFormat::Csv => "csv",
+            ManifestFormat::Json => "json",
+            ManifestFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// 本次运行中文件的变更类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    New,
+    Updated,
+    Deleted,
+}
+
+impl Change {
+    fn label(&self) -> &'static str {
+        match self {
+            Change::New => "新增",
+            Change::Updated => "更新",
+            Change::Deleted => "删除",
+        }
+    }
+}
+
+/// 变更清单中的一条记录
+#[derive(Serialize)]
+struct DeltaEntry<'a> {
+    change: Change,
+    #[serde(flatten)]
+    record: &'a FileRecord,
+}
+
+/// NDJSON 中的一行
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum NdjsonLine<'a, T: Serialize> {
+    File(&'a T),
+    Summary(&'a RunSummary),
+}
+
+/// 写入清单
+pub fn write_manifest(
+    path: &Path,
+    format: ManifestFormat,
+    records: &[FileRecord],
+    summary: &RunSummary,
+) -> Result<()> {
+    match format {
+        ManifestFormat::Csv => write_csv(path, None, records.iter().map(|r| (None, r))),
+        ManifestFormat::Json => write_json(path, records, summary),
+        ManifestFormat::Ndjson => write_ndjson(path, records, summary),
+    }
+}
+
+/// 写入本次运行的变更清单（新增、更新和删除的文件）
+pub fn write_delta_manifest(
+    path: &Path,
+    format: ManifestFormat,
+    changes: &[(Change, &FileRecord)],
+    summary: &RunSummary,
+) -> Result<()> {
+    let entries: Vec<DeltaEntry> = changes
+        .iter()
+        .map(|&(change, record)| DeltaEntry { change, record })
+        .collect();
+    match format {
+        ManifestFormat::Csv => write_csv(
+            path,
+            Some("变更"),
+            changes
+                .iter()
+                .map(|&(change, record)| (Some(change.label()), record)),
+        ),
+        ManifestFormat::Json => write_json(path, &entries, summary),
+        ManifestFormat::Ndjson => write_ndjson(path, &entries, summary),
+    }
+}
+
+/// 单独写入运行摘要（JSON）
+pub fn write_run_report(path: &Path, summary: &RunSummary) -> Result<()> {
+    let mut file = BufWriter::new(AtomicFile::create(path)?);
+    serde_json::to_writer_pretty(&mut file, summary)?;
+    file.write_all(b"\n")?;
+    file.into_inner().map_err(|e| e.into_error())?.commit()
+}
+
+/// 写入 CSV，`change_column` 不为空时在第一列写入变更类型
+fn write_csv<'a>(
+    path: &Path,
+    change_column: Option<&str>,
+    rows: impl Iterator<Item = (Option<&'a str>, &'a FileRecord)>,
+) -> Result<()> {
+    let mut writer = Writer::from_writer(AtomicFile::create(path)?);
+
+    writer.write_record(change_column.into_iter().chain(HEADER))?;
+    for (change, record) in rows {
+        let fields = record_fields(record);
+        writer.write_record(change.map(str::to_string).into_iter().chain(fields))?;
+    }
+
+    writer.into_inner().map_err(|e| e.into_error())?.commit()
+}
+
+fn write_json<T: Serialize>(path: &Path, files: &[T], summary: &RunSummary) -> Result<()> {
+    #[derive(Serialize)]
+    struct Manifest<'a, T> {
+        summary: &'a RunSummary,
+        files: &'a [T],
+    }
+
+    let mut file = BufWriter::new(AtomicFile::create(path)?);
+    serde_json::to_writer_pretty(&mut file, &Manifest { summary, files })?;
+    file.write_all(b"\n")?;
+    file.into_inner().map_err(|e| e.into_error())?.commit()
+}
+
+fn write_ndjson<T: Serialize>(path: &Path, files: &[T], summary: &RunSummary) -> Result<()> {
+    let mut file = BufWriter::new(AtomicFile::create(path)?);
+    for line in files
+        .iter()
+        .map(NdjsonLine::File)
+        .chain([NdjsonLine::Summary(summary)])
+    {
+        serde_json::to_writer(&mut file, &line)?;
+        file.write_all(b"\n")?;
+    }
+    file.into_inner().map_err(|e| e.into_error())?.commit()
+}
+
+fn record_fields(record: &FileRecord) -> [String; 7] {
+    let compression_ratio = if record.original_size > 0 {
+        format!(
+            "{:.2}%",
+            (record.output_size as f64 / record.original_size as f64) * 100.0
+        )
+    } else {
+        "N/A".to_string()
+    };
+
+    [
+        record.relative_path.clone(),
+        record.modified_time.clone(),
+        record.original_hash.clone(),
+        record.output_hash.clone(),
+        record.original_size.to_string(),
+        record.output_size.to_string(),
+        compression_ratio,
+    ]
+}
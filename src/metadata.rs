@@ -0,0 +1,278 @@
+//! 原始文件的元数据，加密保存在容器的数据流开头，还原时写回
+//!
+//! ```text
+//! [FLAGS: 1字节]          bit0 = 含 Unix 权限和所有者，bit1 = 含修改时间
+//! [MODE: 4字节 LE]        仅 bit0，权限位（含 setuid/setgid/sticky）
+//! [UID: 4字节 LE]         仅 bit0
+//! [GID: 4字节 LE]         仅 bit0
+//! [MTIME_SECS: 8字节 LE]  仅 bit1，相对 Unix 纪元的秒数（有符号）
+//! [MTIME_NANOS: 4字节 LE] 仅 bit1
+//! [XATTR_COUNT: 2字节 LE]
+//! 每个扩展属性：[NAME_LEN: 2字节 LE][NAME][VALUE_LEN: 4字节 LE][VALUE]
+//! ```
+
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::{self, File, FileTimes},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const FLAG_UNIX: u8 = 1;
+const FLAG_MTIME: u8 = 1 << 1;
+
+/// 元数据块的最大长度，防止损坏的文件申请过多内存
+pub const MAX_METADATA_LEN: usize = 16 * 1024 * 1024;
+
+/// Unix 权限和所有者
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnixOwnership {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// 原始文件的元数据
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub unix: Option<UnixOwnership>,
+    pub modified: Option<SystemTime>,
+    /// 扩展属性（名称, 值）
+    pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl FileMetadata {
+    /// 读取文件的元数据
+    pub fn capture(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        #[cfg(unix)]
+        let unix = {
+            use std::os::unix::fs::MetadataExt;
+            Some(UnixOwnership {
+                mode: metadata.mode() & 0o7777,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+            })
+        };
+        #[cfg(not(unix))]
+        let unix = None;
+
+        Ok(FileMetadata {
+            unix,
+            modified: metadata.modified().ok(),
+            xattrs: read_xattrs(path),
+        })
+    }
+
+    /// 将元数据写回文件
+    ///
+    /// 权限和修改时间必须成功；所有者和扩展属性在没有权限（非 root）或文件系统不支持时跳过
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        #[cfg(unix)]
+        if let Some(unix) = self.unix {
+            use std::os::unix::fs::PermissionsExt;
+
+            // 先修改所有者，chown 会清除 setuid/setgid 位
+            if let Err(e) = std::os::unix::fs::chown(path, Some(unix.uid), Some(unix.gid))
+                && e.kind() != std::io::ErrorKind::PermissionDenied
+            {
+                return Err(e).context(format!("无法设置文件所有者: {}", path.display()));
+            }
+            fs::set_permissions(path, fs::Permissions::from_mode(unix.mode))
+                .context(format!("无法设置文件权限: {}", path.display()))?;
+        }
+
+        write_xattrs(path, &self.xattrs);
+
+        // 最后设置修改时间，避免被前面的操作改变
+        if let Some(modified) = self.modified {
+            let file = File::options()
+                .write(true)
+                .open(path)
+                .or_else(|_| File::open(path))?;
+            file.set_times(FileTimes::new().set_modified(modified))
+                .context(format!("无法设置修改时间: {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// 编码元数据块，超过 `MAX_METADATA_LEN`（扩展属性过大）时返回错误，
+    /// 否则写出的文件将无法解密
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut flags = 0;
+        if self.unix.is_some() {
+            flags |= FLAG_UNIX;
+        }
+        if self.modified.is_some() {
+            flags |= FLAG_MTIME;
+        }
+
+        let mut data = vec![flags];
+        if let Some(unix) = self.unix {
+            data.extend_from_slice(&unix.mode.to_le_bytes());
+            data.extend_from_slice(&unix.uid.to_le_bytes());
+            data.extend_from_slice(&unix.gid.to_le_bytes());
+        }
+        if let Some(modified) = self.modified {
+            let (secs, nanos) = to_unix_time(modified);
+            data.extend_from_slice(&secs.to_le_bytes());
+            data.extend_from_slice(&nanos.to_le_bytes());
+        }
+
+        // 超出长度限制的扩展属性无法编码，直接跳过
+        let xattrs: Vec<_> = self
+            .xattrs
+            .iter()
+            .filter(|(name, value)| {
+                name.len() <= u16::MAX as usize && value.len() <= u32::MAX as usize
+            })
+            .take(u16::MAX as usize)
+            .collect();
+        data.extend_from_slice(&(xattrs.len() as u16).to_le_bytes());
+        for (name, value) in xattrs {
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(name);
+            data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+
+        if data.len() > MAX_METADATA_LEN {
+            bail!(
+                "元数据长度 {} 字节超过上限 {} 字节（扩展属性过大）",
+                data.len(),
+                MAX_METADATA_LEN
+            );
+        }
+        Ok(data)
+    }
+
+    /// 解码元数据块，返回错误原因
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Cursor { data };
+        let flags = reader.take_u8()?;
+
+        let unix = if flags & FLAG_UNIX != 0 {
+            Some(UnixOwnership {
+                mode: reader.take_u32()?,
+                uid: reader.take_u32()?,
+                gid: reader.take_u32()?,
+            })
+        } else {
+            None
+        };
+        let modified = if flags & FLAG_MTIME != 0 {
+            let secs = i64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+            let nanos = reader.take_u32()?;
+            Some(from_unix_time(secs, nanos)?)
+        } else {
+            None
+        };
+
+        let count = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+        let mut xattrs = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name_len = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+            let name = reader.take(name_len as usize)?.to_vec();
+            let value_len = reader.take_u32()?;
+            let value = reader.take(value_len as usize)?.to_vec();
+            xattrs.push((name, value));
+        }
+
+        if !reader.data.is_empty() {
+            return Err("元数据之后存在多余的数据".to_string());
+        }
+        Ok(FileMetadata {
+            unix,
+            modified,
+            xattrs,
+        })
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let (head, rest) = self
+            .data
+            .split_at_checked(len)
+            .ok_or_else(|| "元数据被截断".to_string())?;
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn take_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+fn to_unix_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+        Err(e) => {
+            // 纪元之前的时间：秒数向下取整，纳秒部分保持非负
+            let before = e.duration();
+            let secs = -(before.as_secs() as i64);
+            match before.subsec_nanos() {
+                0 => (secs, 0),
+                nanos => (secs - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+fn from_unix_time(secs: i64, nanos: u32) -> Result<SystemTime, String> {
+    if nanos >= 1_000_000_000 {
+        return Err("修改时间无效".to_string());
+    }
+    let time = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+            .and_then(|t| t.checked_add(Duration::from_nanos(nanos as u64)))
+    };
+    time.ok_or_else(|| "修改时间无效".to_string())
+}
+
+/// 读取扩展属性，文件系统不支持时返回空
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(names) = xattr::list(path) else {
+        return Vec::new();
+    };
+    names
+        .filter_map(|name| {
+            let value = xattr::get(path, &name).ok()??;
+            Some((name.as_bytes().to_vec(), value))
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(_path: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+    Vec::new()
+}
+
+/// 写回扩展属性，没有权限（如 security/trusted 命名空间）或文件系统不支持时跳过
+#[cfg(unix)]
+fn write_xattrs(path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    for (name, value) in xattrs {
+        let _ = xattr::set(path, OsStr::from_bytes(name), value);
+    }
+}
+
+#[cfg(not(unix))]
+fn write_xattrs(_path: &Path, _xattrs: &[(Vec<u8>, Vec<u8>)]) {}
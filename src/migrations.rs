@@ -0,0 +1,255 @@
+//! 数据库结构迁移
+//!
+//! 数据库版本保存在 `PRAGMA user_version` 中，打开数据库时按顺序执行尚未执行的迁移，
+//! 每个迁移在独立的事务中执行，并在同一事务中更新版本号。
+//!
+//! 引入版本号之前发布的程序创建的数据库版本为 0，包含 files 和 logs 两张表；
+//! 其中 files 表的大小列由程序运行时补充，旧数据库中可能不存在，需要先检查。
+
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, params};
+
+/// 单个迁移
+struct Migration {
+    /// 执行后的数据库版本
+    version: u32,
+    description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+/// 按版本顺序排列的迁移
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "创建文件表和日志表",
+        apply: create_base_tables,
+    },
+    Migration {
+        version: 2,
+        description: "文件表添加大小列",
+        apply: add_size_columns,
+    },
+    Migration {
+        version: 3,
+        description: "文件表添加删除标记列",
+        apply: add_deleted_at,
+    },
+    Migration {
+        version: 4,
+        description: "添加来源表，文件记录按来源隔离",
+        apply: add_sources,
+    },
+    Migration {
+        version: 5,
+        description: "添加目录项表，记录空目录和符号链接",
+        apply: add_entries,
+    },
+    Migration {
+        version: 6,
+        description: "相对路径改为 `/` 分隔并转义 `%` 和 `\\`",
+        apply: escape_paths,
+    },
+    Migration {
+        version: 7,
+        description: "文件表添加对象 ID 列",
+        apply: add_object_id,
+    },
+];
+
+/// 当前程序支持的数据库版本
+pub const SCHEMA_VERSION: u32 = 7;
+
+/// 读取数据库版本
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// 读取数据库版本，高于当前程序支持的版本时报错
+pub fn check_version(conn: &Connection) -> Result<u32> {
+    let current = schema_version(conn)?;
+    if current > SCHEMA_VERSION {
+        bail!(
+            "数据库版本 ({}) 高于当前程序支持的版本 ({})，请升级 hbsx 后再使用该数据库",
+            current,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(current)
+}
+
+/// 执行所有尚未执行的迁移
+pub fn migrate(conn: &Connection) -> Result<()> {
+    let current = check_version(conn)?;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.unchecked_transaction()?;
+        (migration.apply)(&tx).context(format!(
+            "数据库迁移失败 (版本 {}: {})",
+            migration.version, migration.description
+        ))?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn create_base_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS files (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            relative_path TEXT NOT NULL UNIQUE,
+            modified_time TEXT NOT NULL,
+            original_hash TEXT NOT NULL,
+            output_hash TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS logs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_path TEXT NOT NULL,
+            action TEXT NOT NULL,
+            status TEXT NOT NULL,
+            message TEXT,
+            timestamp TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_files_path ON files(relative_path);
+        CREATE INDEX IF NOT EXISTS idx_logs_timestamp ON logs(timestamp);",
+    )?;
+    Ok(())
+}
+
+fn add_size_columns(conn: &Connection) -> Result<()> {
+    for column in ["original_size", "output_size"] {
+        if !has_column(conn, "files", column)? {
+            conn.execute(
+                &format!(
+                    "ALTER TABLE files ADD COLUMN {} INTEGER NOT NULL DEFAULT 0",
+                    column
+                ),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn add_deleted_at(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "files", "deleted_at")? {
+        conn.execute("ALTER TABLE files ADD COLUMN deleted_at TEXT", [])?;
+    }
+    Ok(())
+}
+
+/// 旧表的 relative_path 是全局唯一的，需要重建为按来源唯一。
+/// 旧记录无法确定所属来源，source_id 保留为空
+fn add_sources(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sources (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            input_root TEXT NOT NULL,
+            output_root TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            UNIQUE(input_root, output_root)
+        );
+        CREATE TABLE files_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            source_id INTEGER REFERENCES sources(id),
+            relative_path TEXT NOT NULL,
+            modified_time TEXT NOT NULL,
+            original_hash TEXT NOT NULL,
+            output_hash TEXT NOT NULL,
+            original_size INTEGER NOT NULL DEFAULT 0,
+            output_size INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            deleted_at TEXT,
+            UNIQUE(source_id, relative_path)
+        );",
+    )?;
+
+    conn.execute_batch(
+        "INSERT INTO files_new (id, source_id, relative_path, modified_time, original_hash,
+                                output_hash, original_size, output_size, created_at,
+                                updated_at, deleted_at)
+         SELECT id, NULL, relative_path, modified_time, original_hash, output_hash,
+                original_size, output_size, created_at, updated_at, deleted_at
+         FROM files;
+         DROP TABLE files;
+         ALTER TABLE files_new RENAME TO files;
+         CREATE INDEX IF NOT EXISTS idx_files_path ON files(relative_path);",
+    )?;
+    Ok(())
+}
+
+fn add_entries(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS entries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            source_id INTEGER NOT NULL REFERENCES sources(id),
+            relative_path TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            target TEXT,
+            updated_at TEXT NOT NULL,
+            UNIQUE(source_id, relative_path)
+        );",
+    )?;
+    Ok(())
+}
+
+/// 旧版本按平台分隔符原样保存路径（只能保存 UTF-8 文件名），改为 `crate::pathenc` 的编码。
+/// 旧路径中的 `\` 无法区分是分隔符还是文件名的一部分，按执行迁移的平台处理：
+/// Windows 上视为分隔符，其他平台上视为文件名中的字符
+fn escape_paths(conn: &Connection) -> Result<()> {
+    for (table, column) in [
+        ("files", "relative_path"),
+        ("entries", "relative_path"),
+        ("entries", "target"),
+        ("sources", "input_root"),
+        ("sources", "output_root"),
+    ] {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, {column} FROM {table} WHERE {column} LIKE '%\\%' OR {column} LIKE '%!%%' ESCAPE '!'"
+        ))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        // 先改为临时值再写入新值，避免更新过程中与其他行的旧值冲突（未更新的行不含 `%`）
+        let mut update =
+            conn.prepare(&format!("UPDATE {table} SET {column} = ?2 WHERE id = ?1"))?;
+        for (id, _) in &rows {
+            update.execute(params![id, format!("%migrating-{}", id)])?;
+        }
+        for (id, path) in &rows {
+            update.execute(params![id, escape_legacy_path(path)])?;
+        }
+    }
+    Ok(())
+}
+
+fn escape_legacy_path(path: &str) -> String {
+    let backslash = if cfg!(windows) { "/" } else { "%5C" };
+    path.replace('%', "%25").replace('\\', backslash)
+}
+
+fn add_object_id(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "files", "object_id")? {
+        conn.execute("ALTER TABLE files ADD COLUMN object_id TEXT", [])?;
+    }
+    Ok(())
+}
+
+/// 检查表中是否存在指定列
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in columns {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,70 @@
+//! 加密文件名模式
+//!
+//! 输出文件不再按原始相对路径保存，而是保存为 `objects/<ID 前两位>/<ID>.zstd.enc`，
+//! ID 为随机生成的 128 位十六进制字符串，与文件名和内容无关。
+//! 对象 ID 与原始路径的对应关系（以及空目录和符号链接）保存在输出目录的 `index.hbsx` 中，
+//! 该文件与输出文件使用相同的容器格式加密，没有数据库时也能还原原始目录结构。
+//!
+//! 输出目录中存在 `index.hbsx` 时总是使用该模式。
+
+use crate::container::{decrypt_to_writer, encrypt_bytes};
+use crate::db::{EntryRecord, FileRecord};
+use crate::engine::OUTPUT_SUFFIX;
+use crate::keys::KeyRing;
+use anyhow::{Context, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// 输出目录中存放对象的目录
+pub const OBJECTS_DIR: &str = "objects";
+/// 输出目录中的加密索引文件名
+pub const INDEX_FILE_NAME: &str = "index.hbsx";
+
+/// 加密索引的内容
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NameIndex {
+    /// 使用对象 ID 保存的文件记录（含已删除但保留了输出文件的记录）
+    pub files: Vec<FileRecord>,
+    /// 空目录和符号链接
+    pub entries: Vec<EntryRecord>,
+}
+
+/// 输出目录是否使用加密文件名
+pub fn uses_encrypted_names(output: &Path) -> bool {
+    output.join(INDEX_FILE_NAME).is_file()
+}
+
+/// 生成新的对象 ID
+pub fn new_object_id() -> String {
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    id.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 对象在输出目录中的相对路径
+pub fn object_relative_path(object_id: &str) -> PathBuf {
+    let shard = object_id.get(..2).unwrap_or(object_id);
+    Path::new(OBJECTS_DIR)
+        .join(shard)
+        .join(format!("{}.{}", object_id, OUTPUT_SUFFIX))
+}
+
+/// 读取并解密索引，不存在时返回 None
+pub fn load_index(output: &Path, keys: &KeyRing) -> Result<Option<NameIndex>> {
+    let path = output.join(INDEX_FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let mut data = Vec::new();
+    decrypt_to_writer(&path, &mut data, keys, INDEX_FILE_NAME)?;
+    let index = serde_json::from_slice(&data).context("索引文件格式无效")?;
+    Ok(Some(index))
+}
+
+/// 加密并写入索引
+pub fn write_index(output: &Path, keys: &KeyRing, index: &NameIndex) -> Result<()> {
+    let data = serde_json::to_vec(index)?;
+    encrypt_bytes(&data, &output.join(INDEX_FILE_NAME), keys, INDEX_FILE_NAME)
+}
@@ -0,0 +1,62 @@
+use crate::cli::PasswordArgs;
+use anyhow::{Context, Result, bail};
+use std::io::IsTerminal;
+
+/// 旧版本在未指定密码时使用的默认密码，不能再用于加密新数据
+const DEFAULT_PASSWORD: &str = "default_password";
+
+/// 按 密码文件 > 环境变量 > 交互输入 的顺序获取密码
+///
+/// `encrypt` 为 true 时（加密新数据）交互输入需要再次确认，并拒绝旧版本的默认密码；
+/// 还原和校验时仍接受默认密码，以便读取旧版本生成的文件
+pub fn read_password(args: &PasswordArgs, encrypt: bool) -> Result<String> {
+    let password = if let Some(path) = &args.password_file {
+        let content = std::fs::read_to_string(path)
+            .context(format!("无法读取密码文件: {}", path.display()))?;
+        content.lines().next().unwrap_or_default().to_string()
+    } else if let Ok(password) = std::env::var(&args.password_env) {
+        password
+    } else {
+        prompt_password(encrypt)?
+    };
+
+    validate_password(&password, encrypt)?;
+    Ok(password)
+}
+
+/// 交互式无回显输入密码
+fn prompt_password(confirm: bool) -> Result<String> {
+    if !std::io::stdin().is_terminal() {
+        bail!("未提供密码：请使用 --password-file、环境变量或在终端中交互输入");
+    }
+
+    let password = rpassword::prompt_password("🔐 请输入密码: ")?;
+    if confirm {
+        let again = rpassword::prompt_password("🔐 请再次输入密码: ")?;
+        if password != again {
+            bail!("两次输入的密码不一致");
+        }
+    }
+
+    Ok(password)
+}
+
+/// 拒绝空密码；加密时拒绝旧版本的默认密码，其他情况只给出警告
+fn validate_password(password: &str, encrypt: bool) -> Result<()> {
+    if password.is_empty() {
+        bail!("密码不能为空");
+    }
+    if password == DEFAULT_PASSWORD {
+        if encrypt {
+            bail!(
+                "拒绝使用默认密码 \"{}\" 加密，请设置自己的密码",
+                DEFAULT_PASSWORD
+            );
+        }
+        eprintln!(
+            "⚠️ 正在使用旧版本的默认密码 \"{}\"，建议还原后用自己的密码重新加密",
+            DEFAULT_PASSWORD
+        );
+    }
+    Ok(())
+}
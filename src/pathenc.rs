@@ -0,0 +1,119 @@
+//! 数据库中相对路径的编码
+//!
+//! 相对路径以 `/` 分隔保存，与平台无关，可以在 Linux 和 Windows 之间共用数据库。
+//! 文件名中无法表示为 UTF-8 的字节，以及 `%` 和 `\` 本身，转义为 `%XX`（大写十六进制），
+//! 因此任意文件名都能无损保存和还原：
+//!
+//! ```text
+//! docs/a b.txt        -> docs/a b.txt
+//! 100%.txt            -> 100%25.txt
+//! caf\xE9.txt (Latin-1) -> caf%E9.txt
+//! ```
+
+use anyhow::{Result, bail};
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+/// 将相对路径编码为数据库中保存的形式（符号链接的目标可能是绝对路径，此时以 `/` 开头）
+pub fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir => encoded.push('/'),
+            Component::CurDir => {}
+            other => {
+                if !encoded.is_empty() && !encoded.ends_with('/') {
+                    encoded.push('/');
+                }
+                encode_component(other.as_os_str(), &mut encoded);
+            }
+        }
+    }
+    encoded
+}
+
+fn encode_component(name: &OsStr, encoded: &mut String) {
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '%' | '\\' => encoded.push_str(&format!("%{:02X}", c as u32)),
+                c => encoded.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+}
+
+/// 将数据库中保存的路径还原为本地路径
+///
+/// 非 Unix 平台上无法表示的字节会被替换为 U+FFFD
+pub fn decode_path(encoded: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    if encoded.starts_with('/') {
+        path.push(Component::RootDir);
+    }
+    for component in encoded.split('/').filter(|c| !c.is_empty()) {
+        path.push(decode_component(component));
+    }
+    path
+}
+
+/// 将数据库中保存的相对路径还原为本地路径，用于拼接到目标目录下
+///
+/// 解码后含有 `..`、根目录或盘符的路径（包括由 `%2F` 等转义解码出的分隔符组成的）会越出目标目录，返回错误
+pub fn decode_relative_path(encoded: &str) -> Result<PathBuf> {
+    let path = decode_path(encoded);
+    let normal = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !normal || path.as_os_str().is_empty() {
+        bail!("无效的相对路径: {}", encoded);
+    }
+    Ok(path)
+}
+
+fn decode_component(component: &str) -> PathBuf {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 3) {
+            Some([b'%', high, low]) if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                Some(hex_value(*high) << 4 | hex_value(*low))
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    bytes_to_path(decoded)
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: Vec<u8>) -> PathBuf {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
@@ -0,0 +1,298 @@
+//! 遍历输入目录，应用忽略规则和文件过滤条件
+//!
+//! 忽略规则决定哪些文件属于输入目录（被忽略的文件视为不存在，已加密的记录会被标记为删除）：
+//! - 任意层级的 `.xorignore` 文件（gitignore 语法）
+//! - `--include`/`--exclude` glob（相对于输入目录）
+//! - 位于输入目录中的输出目录和数据库文件
+//!
+//! 大小和时间过滤只决定本次是否处理文件，不在范围内的文件保留已有记录。
+//!
+//! 除普通文件外，遍历还会返回：
+//! - 按 [`SymlinkPolicy::Store`] 保存的符号链接
+//! - 不含任何可还原内容的目录（还原时需要单独创建）
+//! - 跳过的特殊文件（FIFO、socket、设备文件）和符号链接，由调用方记录日志
+
+use anyhow::{Context, Result};
+use ignore::{WalkBuilder, overrides::OverrideBuilder};
+use std::{
+    collections::HashSet,
+    fs::{self, FileType, Metadata},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// 忽略规则文件名
+pub const IGNORE_FILE_NAME: &str = ".xorignore";
+
+/// 符号链接的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// 跟随链接，按链接指向的文件或目录处理（失效或循环的链接会被跳过）
+    Follow,
+    /// 不跟随，将链接本身（指向的路径）记录到数据库，还原时重新创建
+    Store,
+    /// 跳过链接并记录日志
+    #[default]
+    Skip,
+}
+
+/// 文件过滤条件
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    /// 只包含匹配的文件（为空时包含所有文件）
+    pub include: Vec<String>,
+    /// 排除匹配的文件或目录
+    pub exclude: Vec<String>,
+    /// 跳过小于该大小的文件（字节）
+    pub min_size: Option<u64>,
+    /// 跳过大于该大小的文件（字节）
+    pub max_size: Option<u64>,
+    /// 跳过最近该时间内修改的文件（例如仍在写入的文件）
+    pub min_age: Option<Duration>,
+    /// 跳过超过该时间未修改的文件
+    pub max_age: Option<Duration>,
+}
+
+impl FileFilter {
+    /// 文件大小和修改时间是否在过滤范围内
+    pub fn accepts(&self, metadata: &Metadata, now: SystemTime) -> bool {
+        let size = metadata.len();
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        if self.min_age.is_none() && self.max_age.is_none() {
+            return true;
+        }
+        // 修改时间在未来时视为刚修改
+        let age = metadata
+            .modified()
+            .ok()
+            .map(|modified| now.duration_since(modified).unwrap_or_default());
+        match age {
+            Some(age) => {
+                !(self.min_age.is_some_and(|min| age < min)
+                    || self.max_age.is_some_and(|max| age > max))
+            }
+            None => true,
+        }
+    }
+}
+
+/// 遍历时跳过的条目
+#[derive(Debug, Clone)]
+pub struct SkippedEntry {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// 输入目录的遍历结果
+pub struct WalkResult {
+    /// 符合忽略规则的所有文件
+    pub files: Vec<PathBuf>,
+    /// 需要保存的符号链接（仅 [`SymlinkPolicy::Store`]）
+    pub symlinks: Vec<PathBuf>,
+    /// 不含文件、符号链接和子目录的目录（不含输入目录本身）
+    pub empty_dirs: Vec<PathBuf>,
+    /// 跳过的特殊文件和符号链接
+    pub skipped: Vec<SkippedEntry>,
+    /// 是否完整遍历（有目录或忽略规则文件无法读取时为 false）
+    pub complete: bool,
+}
+
+/// 遍历输入目录，返回未被忽略的条目。`excluded` 中位于输入目录内的路径会被跳过
+pub fn walk_input(
+    input: &Path,
+    filter: &FileFilter,
+    symlinks: SymlinkPolicy,
+    excluded: &[&Path],
+) -> Result<WalkResult> {
+    let mut overrides = OverrideBuilder::new(input);
+    for glob in &filter.include {
+        overrides
+            .add(glob)
+            .context(format!("无效的 --include 规则: {}", glob))?;
+    }
+    for glob in &filter.exclude {
+        overrides
+            .add(&format!("!{}", glob))
+            .context(format!("无效的 --exclude 规则: {}", glob))?;
+    }
+
+    let excluded = excluded_paths(input, excluded)?;
+    let walker = WalkBuilder::new(input)
+        .standard_filters(false)
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .overrides(overrides.build()?)
+        .follow_links(symlinks == SymlinkPolicy::Follow)
+        .filter_entry(move |entry| !excluded.iter().any(|path| path == entry.path()))
+        .build();
+
+    let mut result = WalkResult {
+        files: Vec::new(),
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        skipped: Vec::new(),
+        complete: true,
+    };
+    let mut dirs = Vec::new();
+    // 含有会被还原的条目的目录
+    let mut non_empty = HashSet::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                // 跟随链接时，失效或循环的链接不影响遍历的完整性
+                match broken_link(&e) {
+                    Some(skipped) => result.skipped.push(skipped),
+                    None => result.complete = false,
+                }
+                continue;
+            }
+        };
+        let Some(file_type) = entry.file_type() else {
+            continue;
+        };
+        if entry.depth() == 0 {
+            continue;
+        }
+
+        if file_type.is_symlink() {
+            if symlinks == SymlinkPolicy::Store {
+                result.symlinks.push(entry.path().to_path_buf());
+            } else {
+                result.skipped.push(SkippedEntry {
+                    path: entry.into_path(),
+                    reason: "符号链接".to_string(),
+                });
+                continue;
+            }
+        } else if file_type.is_dir() {
+            dirs.push(entry.path().to_path_buf());
+        } else if file_type.is_file() {
+            result.files.push(entry.path().to_path_buf());
+        } else {
+            result.skipped.push(SkippedEntry {
+                reason: special_file_kind(&file_type).to_string(),
+                path: entry.into_path(),
+            });
+            continue;
+        }
+        if let Some(parent) = entry.path().parent() {
+            non_empty.insert(parent.to_path_buf());
+        }
+    }
+
+    result.empty_dirs = dirs
+        .into_iter()
+        .filter(|dir| !non_empty.contains(dir))
+        .collect();
+    Ok(result)
+}
+
+/// 跟随链接时遇到的失效或循环链接
+fn broken_link(error: &ignore::Error) -> Option<SkippedEntry> {
+    match error {
+        ignore::Error::WithDepth { err, .. } => broken_link(err),
+        ignore::Error::Loop { child, .. } => Some(SkippedEntry {
+            path: child.clone(),
+            reason: "符号链接循环".to_string(),
+        }),
+        ignore::Error::WithPath { path, .. }
+            if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) =>
+        {
+            Some(SkippedEntry {
+                path: path.clone(),
+                reason: "符号链接已失效".to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// 特殊文件的类型名称
+#[cfg(unix)]
+fn special_file_kind(file_type: &FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_fifo() {
+        "特殊文件 (FIFO)"
+    } else if file_type.is_socket() {
+        "特殊文件 (socket)"
+    } else if file_type.is_block_device() {
+        "特殊文件 (块设备)"
+    } else if file_type.is_char_device() {
+        "特殊文件 (字符设备)"
+    } else {
+        "特殊文件"
+    }
+}
+
+#[cfg(not(unix))]
+fn special_file_kind(_file_type: &FileType) -> &'static str {
+    "特殊文件"
+}
+
+/// 将位于输入目录中的路径转换为遍历时看到的路径（遍历不解析符号链接，需按规范化路径比较）
+fn excluded_paths(input: &Path, paths: &[&Path]) -> Result<Vec<PathBuf>> {
+    let canonical_input =
+        fs::canonicalize(input).context(format!("无法访问输入目录: {}", input.display()))?;
+
+    let mut excluded = Vec::new();
+    for path in paths {
+        let Ok(canonical) = fs::canonicalize(path) else {
+            continue;
+        };
+        if let Ok(relative) = canonical.strip_prefix(&canonical_input) {
+            excluded.push(input.join(relative));
+        }
+    }
+    Ok(excluded)
+}
+
+/// 解析大小，如 `512`、`64K`、`10M`、`2G`（1K = 1024 字节）
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = split_unit(value);
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        "T" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("无效的大小单位: {}", value)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("无效的大小: {}", value))
+}
+
+/// 解析时长，如 `30s`、`15m`、`12h`、`7d`（不带单位时为秒）
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = split_unit(value);
+    let seconds: u64 = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("无效的时间单位: {}", value)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(seconds))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("无效的时长: {}", value))
+}
+
+fn split_unit(value: &str) -> (&str, &str) {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    value.split_at(split)
+}
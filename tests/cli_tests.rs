@@ -0,0 +1,693 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit},
+};
+use anyhow::Result;
+use hbsx::container::{MAGIC, NONCE_LEN};
+use hbsx::keys::PBKDF2_ITERS;
+use pbkdf2::pbkdf2_hmac;
+use rusqlite::Connection;
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+/// 运行二进制程序，使用临时目录作为用户主目录以隔离数据库（同时忽略外部设置的 XOR_DB）
+fn run(home: &Path, args: &[&str]) -> Result<Output> {
+    run_with_password(home, "secret", args)
+}
+
+/// 通过环境变量传入密码运行二进制程序
+fn run_with_password(home: &Path, password: &str, args: &[&str]) -> Result<Output> {
+    let output = Command::new(env!("CARGO_BIN_EXE_hbsx"))
+        .args(args)
+        .env("HOME", home)
+        .env("USERPROFILE", home)
+        .env_remove("XOR_DB")
+        .env("XOR_PASSWORD", password)
+        .stdin(Stdio::null())
+        .output()?;
+    Ok(output)
+}
+
+/// 创建包含几个测试文件的输入目录
+fn create_input(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir.join("sub"))?;
+    fs::write(dir.join("a.txt"), b"Hello, World!")?;
+    fs::write(dir.join("sub").join("b.bin"), vec![7u8; 10_000])?;
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_then_restore() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    let restored = temp_dir.path().join("restored");
+    create_input(&input)?;
+
+    let result = run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+
+    let result = run(
+        &home,
+        &[
+            "restore",
+            "-i",
+            output.to_str().unwrap(),
+            "-o",
+            restored.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+
+    assert_eq!(fs::read(restored.join("a.txt"))?, b"Hello, World!");
+    assert_eq!(
+        fs::read(restored.join("sub").join("b.bin"))?,
+        vec![7u8; 10_000]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_reports_wrong_password() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    let restored = temp_dir.path().join("restored");
+    create_input(&input)?;
+
+    run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+
+    let result = run_with_password(
+        &home,
+        "wrong",
+        &[
+            "restore",
+            "-i",
+            output.to_str().unwrap(),
+            "-o",
+            restored.to_str().unwrap(),
+        ],
+    )?;
+    assert!(!result.status.success());
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("密码错误"));
+    assert!(!stderr.contains("文件损坏"));
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_reports_corrupted_file() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    let restored = temp_dir.path().join("restored");
+    create_input(&input)?;
+
+    run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+
+    // 篡改密文的最后一个字节
+    let encrypted = output.join("a.txt.zstd.enc");
+    let mut data = fs::read(&encrypted)?;
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    fs::write(&encrypted, data)?;
+
+    let result = run(
+        &home,
+        &[
+            "restore",
+            "-i",
+            output.to_str().unwrap(),
+            "-o",
+            restored.to_str().unwrap(),
+        ],
+    )?;
+    // 部分文件还原失败
+    assert_eq!(result.status.code(), Some(2));
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("文件损坏"));
+    assert_eq!(
+        fs::read(restored.join("sub").join("b.bin"))?,
+        vec![7u8; 10_000]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_requires_password() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+
+    // 没有环境变量、密码文件，且标准输入不是终端
+    let result = Command::new(env!("CARGO_BIN_EXE_hbsx"))
+        .args([
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ])
+        .env("HOME", &home)
+        .env("USERPROFILE", &home)
+        .env_remove("XOR_DB")
+        .env_remove("XOR_PASSWORD")
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!result.status.success());
+    assert!(!output.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_rejects_default_password() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+
+    let result = run_with_password(
+        &home,
+        "default_password",
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+    assert!(!result.status.success());
+    assert!(!output.exists());
+
+    // 旧版本用默认密码生成的文件仍可还原，只给出警告
+    let legacy = temp_dir.path().join("legacy");
+    fs::create_dir_all(&legacy)?;
+    let salt = [1u8; 16];
+    let nonce = [2u8; NONCE_LEN];
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(b"default_password", &salt, PBKDF2_ITERS, &mut key);
+    let compressed = zstd::stream::encode_all(&b"legacy content"[..], 3)?;
+    let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), compressed.as_ref())
+        .unwrap();
+    let mut data = MAGIC.to_vec();
+    data.push(1);
+    data.push(salt.len() as u8);
+    data.extend_from_slice(&salt);
+    data.push(NONCE_LEN as u8);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    fs::write(legacy.join("notes.zstd.enc"), data)?;
+
+    let restored = temp_dir.path().join("restored");
+    let result = run_with_password(
+        &home,
+        "default_password",
+        &[
+            "restore",
+            "-i",
+            legacy.to_str().unwrap(),
+            "-o",
+            restored.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("默认密码"));
+    assert_eq!(fs::read(restored.join("notes"))?, b"legacy content");
+
+    Ok(())
+}
+
+#[test]
+fn test_password_file() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    let restored = temp_dir.path().join("restored");
+    let password_file = temp_dir.path().join("password.txt");
+    create_input(&input)?;
+    fs::write(&password_file, "from-file\n")?;
+
+    // 密码文件优先于环境变量
+    let result = run_with_password(
+        &home,
+        "from-env",
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--password-file",
+            password_file.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+
+    let result = run_with_password(
+        &home,
+        "from-file",
+        &[
+            "restore",
+            "-i",
+            output.to_str().unwrap(),
+            "-o",
+            restored.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+    assert_eq!(fs::read(restored.join("a.txt"))?, b"Hello, World!");
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_rejects_missing_input() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("missing");
+    let output = temp_dir.path().join("output");
+
+    let result = run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("目录不存在"));
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_detects_modified_output() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+
+    run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+
+    let result = run(&home, &["verify", "-o", output.to_str().unwrap()])?;
+    assert!(result.status.success());
+
+    let result = run(&home, &["verify", "--deep", "-o", output.to_str().unwrap()])?;
+    assert!(result.status.success());
+
+    fs::write(output.join("a.txt.zstd.enc"), b"garbage")?;
+
+    let result = run(&home, &["verify", "--deep", "-o", output.to_str().unwrap()])?;
+    assert_eq!(result.status.code(), Some(4));
+
+    let result = run(&home, &["verify", "-o", output.to_str().unwrap()])?;
+    assert_eq!(result.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("a.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn test_database_location_options() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    let db = temp_dir.path().join("ci").join("catalog.sqlite");
+    create_input(&input)?;
+
+    // --db 参数
+    let result = run(
+        &home,
+        &[
+            "--db",
+            db.to_str().unwrap(),
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+    assert!(db.exists());
+    assert!(!home.join(".xor").exists());
+
+    // XOR_DB 环境变量
+    let result = Command::new(env!("CARGO_BIN_EXE_hbsx"))
+        .args(["verify", "-o", output.to_str().unwrap()])
+        .env("HOME", &home)
+        .env("USERPROFILE", &home)
+        .env("XOR_DB", &db)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stdout).contains("校验通过"));
+    assert!(!home.join(".xor").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_catalog_in_output_travels_with_tree() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    let moved = temp_dir.path().join("moved");
+    let restored = temp_dir.path().join("restored");
+    create_input(&input)?;
+
+    let result = run(
+        &home,
+        &[
+            "encrypt",
+            "--catalog-in-output",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+    assert!(output.join("catalog.db").exists());
+    assert!(!home.join(".xor").exists());
+
+    // 移动加密目录后仍能使用其中的目录数据库校验和还原
+    fs::rename(&output, &moved)?;
+    let result = run(&home, &["verify", "-o", moved.to_str().unwrap()])?;
+    assert!(result.status.success());
+
+    let result = run(
+        &home,
+        &[
+            "restore",
+            "-i",
+            moved.to_str().unwrap(),
+            "-o",
+            restored.to_str().unwrap(),
+        ],
+    )?;
+    assert!(result.status.success());
+    assert_eq!(fs::read(restored.join("a.txt"))?, b"Hello, World!");
+    assert!(!home.join(".xor").exists());
+
+    // 目录数据库中保存着明文路径，加密文件名时拒绝使用
+    let result = run(
+        &home,
+        &[
+            "encrypt",
+            "--encrypt-names",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            moved.to_str().unwrap(),
+        ],
+    )?;
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("目录数据库"));
+    assert!(!moved.join("index.hbsx").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_partial_failure_exit_code() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+
+    // 输出路径被目录占用，a.txt 无法写入
+    fs::create_dir_all(output.join("a.txt.zstd.enc"))?;
+
+    let result = run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+    assert_eq!(result.status.code(), Some(2));
+
+    // 结束时列出失败的文件
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("处理失败 1 个文件"));
+    assert!(stderr.contains("a.txt"));
+    assert!(output.join("sub").join("b.bin.zstd.enc").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_total_failure_exit_code() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+
+    // 两个输出路径都被目录占用，所有文件都无法写入
+    fs::create_dir_all(output.join("a.txt.zstd.enc"))?;
+    fs::create_dir_all(output.join("sub").join("b.bin.zstd.enc"))?;
+
+    let result = run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ],
+    )?;
+    assert_eq!(result.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("处理失败 2 个文件"));
+
+    Ok(())
+}
+
+#[test]
+fn test_argument_error_exit_code() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+
+    // 参数错误以 1 退出，与部分失败的 2 区分
+    let result = run(&home, &["encrypt", "--no-such-option"])?;
+    assert_eq!(result.status.code(), Some(1));
+    assert!(!result.stderr.is_empty());
+
+    // 超出范围的 KDF 参数在解析参数时拒绝，不会写出密钥文件
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+    for option in ["--argon2-parallelism=0", "--pbkdf2-iterations=0"] {
+        let result = run(
+            &home,
+            &[
+                "encrypt",
+                option,
+                "-i",
+                input.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+            ],
+        )?;
+        assert_eq!(result.status.code(), Some(1));
+        assert!(!output.join("xor.key").exists());
+    }
+
+    let result = run(&home, &["--help"])?;
+    assert!(result.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_kdf_options_must_match_key_file() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+    let encrypt = |options: &[&str]| {
+        let mut args = vec![
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ];
+        args.extend_from_slice(options);
+        run(&home, &args)
+    };
+
+    assert!(
+        encrypt(&["--kdf", "pbkdf2", "--pbkdf2-iterations", "2000"])?
+            .status
+            .success()
+    );
+    let key = fs::read(output.join("xor.key"))?;
+
+    // 不指定参数或指定的参数与密钥文件一致时沿用密钥文件
+    assert!(encrypt(&[])?.status.success());
+    assert!(encrypt(&["--kdf", "pbkdf2"])?.status.success());
+    assert!(encrypt(&["--pbkdf2-iterations", "2000"])?.status.success());
+
+    // 与密钥文件不一致的参数报错，而不是被忽略
+    fs::write(input.join("new.txt"), b"new")?;
+    for options in [
+        &["--pbkdf2-iterations", "3000"][..],
+        &["--kdf", "argon2id"],
+        &["--argon2-memory", "2048"],
+    ] {
+        let result = encrypt(options)?;
+        assert_eq!(result.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&result.stderr).contains("KDF"));
+        let mut dry_run = options.to_vec();
+        dry_run.push("--dry-run");
+        assert_eq!(encrypt(&dry_run)?.status.code(), Some(1));
+    }
+    assert_eq!(fs::read(output.join("xor.key"))?, key);
+    assert!(!output.join("new.txt.zstd.enc").exists());
+
+    // 参数组合无效
+    let result = encrypt(&["--kdf", "argon2id", "--pbkdf2-iterations", "2000"])?;
+    assert_eq!(result.status.code(), Some(1));
+
+    Ok(())
+}
+
+#[test]
+fn test_dry_run_json_plan() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let home = temp_dir.path().join("home");
+    let input = temp_dir.path().join("input");
+    let output = temp_dir.path().join("output");
+    create_input(&input)?;
+
+    // 预演不需要密码
+    let result = Command::new(env!("CARGO_BIN_EXE_hbsx"))
+        .args([
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--dry-run",
+            "--json",
+        ])
+        .env("HOME", &home)
+        .env("USERPROFILE", &home)
+        .env_remove("XOR_DB")
+        .env_remove("XOR_PASSWORD")
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(result.status.success());
+
+    let plan: serde_json::Value = serde_json::from_slice(&result.stdout)?;
+    assert_eq!(plan["counts"]["new"], 2);
+    assert_eq!(plan["files"].as_array().unwrap().len(), 2);
+    assert!(!output.exists());
+    // 不创建默认位置的数据库
+    assert!(!home.join(".xor").exists());
+
+    // 旧版本的数据库按空数据库处理，不执行迁移
+    let db = temp_dir.path().join("legacy.db");
+    let conn = Connection::open(&db)?;
+    conn.execute_batch(
+        "CREATE TABLE files (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            relative_path TEXT NOT NULL UNIQUE,
+            modified_time TEXT NOT NULL,
+            original_hash TEXT NOT NULL,
+            output_hash TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );",
+    )?;
+    drop(conn);
+    let before = fs::read(&db)?;
+    let result = run(
+        &home,
+        &[
+            "encrypt",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--db",
+            db.to_str().unwrap(),
+            "--dry-run",
+            "--json",
+        ],
+    )?;
+    assert!(result.status.success());
+    let plan: serde_json::Value = serde_json::from_slice(&result.stdout)?;
+    assert_eq!(plan["counts"]["new"], 2);
+    assert_eq!(fs::read(&db)?, before);
+
+    Ok(())
+}
@@ -0,0 +1,460 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit},
+};
+use anyhow::Result;
+use hbsx::container::{
+    ContainerError, MAGIC, NONCE_LEN, SEGMENT_SIZE, TAG_LEN, compress_and_encrypt_mt,
+    decrypt_and_decompress,
+};
+use hbsx::fsutil::{TEMP_FILE_MARKER, write_atomic};
+use hbsx::keys::{KEY_FILE_NAME, Kdf, KeyRing, MasterKey, PBKDF2_ITERS, stored_kdf};
+use hbsx::metadata::{FileMetadata, MAX_METADATA_LEN, UnixOwnership};
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use sha2::Sha256;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tempfile::TempDir;
+
+/// 容器头长度：MAGIC + VERSION + KEY_SCHEME + KDF(Argon2id) + SALT_LEN + SALT + CHECK + PREFIX_LEN + PREFIX + SEGMENT_SIZE
+const HEADER_LEN: usize = 4 + 1 + 1 + (2 + 12) + 1 + 16 + 32 + 1 + 7 + 4;
+
+/// 测试使用较小的 Argon2id 参数以加快速度
+const TEST_KDF: Kdf = Kdf::Argon2id {
+    memory_kib: 1024,
+    iterations: 1,
+    parallelism: 1,
+};
+
+fn password(password: &str) -> KeyRing {
+    KeyRing::with_kdf(password, TEST_KDF)
+}
+
+/// 生成不可压缩的随机数据，保证压缩后仍跨越多个分段
+fn random_data(len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut data);
+    data
+}
+
+fn roundtrip(dir: &Path, data: &[u8]) -> Result<Vec<u8>> {
+    let input = dir.join("input.bin");
+    let encrypted = dir.join("input.bin.zstd.enc");
+    let restored = dir.join("restored.bin");
+    fs::write(&input, data)?;
+
+    compress_and_encrypt_mt(&input, &encrypted, &password("secret"), "input")?;
+    decrypt_and_decompress(&encrypted, &restored, &password("secret"), "input")?;
+
+    Ok(fs::read(&restored)?)
+}
+
+fn container_error(result: Result<()>) -> ContainerError {
+    match result.unwrap_err().downcast::<ContainerError>() {
+        Ok(e) => e,
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_roundtrip_small_and_empty() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+
+    assert_eq!(
+        roundtrip(temp_dir.path(), b"Hello, World!")?,
+        b"Hello, World!"
+    );
+    assert_eq!(roundtrip(temp_dir.path(), b"")?, b"");
+
+    Ok(())
+}
+
+#[test]
+fn test_roundtrip_multiple_segments() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let data = random_data(SEGMENT_SIZE * 2 + 1234);
+
+    assert_eq!(roundtrip(temp_dir.path(), &data)?, data);
+
+    Ok(())
+}
+
+#[test]
+fn test_wrong_password() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.txt");
+    let encrypted = temp_dir.path().join("input.txt.zstd.enc");
+    let restored = temp_dir.path().join("restored.txt");
+    fs::write(&input, b"Hello, World!")?;
+
+    compress_and_encrypt_mt(&input, &encrypted, &password("secret"), "input")?;
+    let result = decrypt_and_decompress(&encrypted, &restored, &password("wrong"), "input");
+
+    assert!(matches!(
+        container_error(result),
+        ContainerError::WrongPassword
+    ));
+    assert!(!restored.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_truncation_at_segment_boundary() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.bin");
+    let encrypted = temp_dir.path().join("input.bin.zstd.enc");
+    let restored = temp_dir.path().join("restored.bin");
+    fs::write(&input, random_data(SEGMENT_SIZE * 2 + 1234))?;
+    compress_and_encrypt_mt(&input, &encrypted, &password("secret"), "input")?;
+
+    // 只保留第一段
+    let data = fs::read(&encrypted)?;
+    fs::write(&encrypted, &data[..HEADER_LEN + SEGMENT_SIZE + TAG_LEN])?;
+
+    let result = decrypt_and_decompress(&encrypted, &restored, &password("secret"), "input");
+    assert!(matches!(
+        container_error(result),
+        ContainerError::Corrupted(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_tampered_later_segment() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.bin");
+    let encrypted = temp_dir.path().join("input.bin.zstd.enc");
+    let restored = temp_dir.path().join("restored.bin");
+    fs::write(&input, random_data(SEGMENT_SIZE * 2 + 1234))?;
+    compress_and_encrypt_mt(&input, &encrypted, &password("secret"), "input")?;
+
+    let mut data = fs::read(&encrypted)?;
+    data[HEADER_LEN + SEGMENT_SIZE + TAG_LEN + 10] ^= 0xff;
+    fs::write(&encrypted, data)?;
+
+    let result = decrypt_and_decompress(&encrypted, &restored, &password("secret"), "input");
+    assert!(matches!(
+        container_error(result),
+        ContainerError::Corrupted(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_decrypt_version_1() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let encrypted = temp_dir.path().join("legacy.zstd.enc");
+    let restored = temp_dir.path().join("legacy.txt");
+
+    // 按 VERSION 1 格式手工构造文件
+    let salt = [1u8; 16];
+    let nonce = [2u8; NONCE_LEN];
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(b"secret", &salt, PBKDF2_ITERS, &mut key);
+    let compressed = zstd::stream::encode_all(&b"legacy content"[..], 3)?;
+    let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), compressed.as_ref())
+        .unwrap();
+
+    let mut data = MAGIC.to_vec();
+    data.push(1);
+    data.push(salt.len() as u8);
+    data.extend_from_slice(&salt);
+    data.push(NONCE_LEN as u8);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    fs::write(&encrypted, data)?;
+
+    decrypt_and_decompress(&encrypted, &restored, &password("secret"), "input")?;
+    assert_eq!(fs::read(&restored)?, b"legacy content");
+
+    Ok(())
+}
+
+#[test]
+fn test_master_key_roundtrip() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.txt");
+    let encrypted = temp_dir.path().join("input.txt.zstd.enc");
+    let restored = temp_dir.path().join("restored.txt");
+    fs::write(&input, b"Hello, World!")?;
+
+    let master = MasterKey::load_or_create(temp_dir.path(), "secret", Some(TEST_KDF))?;
+    assert!(temp_dir.path().join(KEY_FILE_NAME).exists());
+    compress_and_encrypt_mt(
+        &input,
+        &encrypted,
+        &KeyRing::with_master_key("secret", master),
+        "input",
+    )?;
+
+    // 再次读取密钥文件得到相同的主密钥
+    let master = MasterKey::load(temp_dir.path(), "secret")?.unwrap();
+    decrypt_and_decompress(
+        &encrypted,
+        &restored,
+        &KeyRing::with_master_key("secret", master),
+        "input",
+    )?;
+    assert_eq!(fs::read(&restored)?, b"Hello, World!");
+
+    // 没有密钥文件时无法解密
+    let result = decrypt_and_decompress(&encrypted, &restored, &password("secret"), "input");
+    assert!(matches!(
+        container_error(result),
+        ContainerError::MissingKeyFile
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_key_file_rejects_wrong_password() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    MasterKey::load_or_create(temp_dir.path(), "secret", Some(TEST_KDF))?;
+
+    let error = MasterKey::load(temp_dir.path(), "wrong").err().unwrap();
+    assert!(matches!(
+        error.downcast_ref::<ContainerError>(),
+        Some(ContainerError::WrongPassword)
+    ));
+
+    // 已有密钥文件时不能修改 KDF 参数，不指定时沿用
+    assert_eq!(stored_kdf(temp_dir.path())?, Some(TEST_KDF));
+    assert!(MasterKey::load_or_create(temp_dir.path(), "secret", Some(Kdf::LEGACY)).is_err());
+    let master = MasterKey::load_or_create(temp_dir.path(), "secret", None)?;
+    assert_eq!(master.kdf(), TEST_KDF);
+
+    Ok(())
+}
+
+#[test]
+fn test_kdf_encode_decode() {
+    for kdf in [TEST_KDF, Kdf::default(), Kdf::LEGACY] {
+        let encoded = kdf.encode();
+        assert_eq!(encoded[1] as usize, encoded.len() - 2);
+        assert_eq!(Kdf::decode(encoded[0], &encoded[2..]), Ok(kdf));
+    }
+
+    assert!(Kdf::decode(9, &[]).is_err());
+    assert!(Kdf::decode(2, &[0u8; 4]).is_err());
+
+    // 超出范围的参数：0 次迭代、0 并行度、过大的迭代次数
+    let argon2 = |memory_kib, iterations, parallelism| Kdf::Argon2id {
+        memory_kib,
+        iterations,
+        parallelism,
+    };
+    let out_of_range = [
+        Kdf::Pbkdf2 { iterations: 0 },
+        Kdf::Pbkdf2 {
+            iterations: u32::MAX,
+        },
+        argon2(1024, 1, 0),
+        argon2(1024, u32::MAX, 1),
+        argon2(u32::MAX, 1, 1),
+    ];
+    for kdf in out_of_range {
+        let encoded = kdf.encode();
+        assert!(Kdf::decode(encoded[0], &encoded[2..]).is_err());
+        assert!(kdf.derive("secret", &[0u8; 16]).is_err());
+    }
+}
+
+#[test]
+fn test_kdf_params_read_from_header() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.txt");
+    let encrypted = temp_dir.path().join("input.txt.zstd.enc");
+    let restored = temp_dir.path().join("restored.txt");
+    fs::write(&input, b"Hello, World!")?;
+
+    let pbkdf2 = Kdf::Pbkdf2 { iterations: 1000 };
+    compress_and_encrypt_mt(
+        &input,
+        &encrypted,
+        &KeyRing::with_kdf("secret", pbkdf2),
+        "input",
+    )?;
+
+    // 解密时使用文件头中记录的参数，而不是 KeyRing 的 KDF
+    decrypt_and_decompress(&encrypted, &restored, &password("secret"), "input")?;
+    assert_eq!(fs::read(&restored)?, b"Hello, World!");
+
+    Ok(())
+}
+
+#[test]
+fn test_relative_path_is_authenticated() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.txt");
+    let encrypted = temp_dir.path().join("input.txt.zstd.enc");
+    let restored = temp_dir.path().join("restored.txt");
+    fs::write(&input, b"Hello, World!")?;
+
+    // 主密钥和从密码派生两种方案的报告一致
+    let master = MasterKey::load_or_create(temp_dir.path(), "secret", Some(TEST_KDF))?;
+    for keys in [
+        KeyRing::with_master_key("secret", master),
+        password("secret"),
+    ] {
+        compress_and_encrypt_mt(&input, &encrypted, &keys, "a/input.txt.zstd.enc")?;
+
+        // 路径不一致（例如文件被移动或与其他文件互换）时认证失败，报告为文件损坏而不是密码错误
+        let result = decrypt_and_decompress(&encrypted, &restored, &keys, "b/input.txt.zstd.enc");
+        assert!(matches!(
+            container_error(result),
+            ContainerError::Corrupted(_)
+        ));
+        assert!(!restored.exists());
+
+        decrypt_and_decompress(&encrypted, &restored, &keys, "a/input.txt.zstd.enc")?;
+        assert_eq!(fs::read(&restored)?, b"Hello, World!");
+        fs::remove_file(&restored)?;
+    }
+
+    Ok(())
+}
+
+/// 目录中 `AtomicFile` 遗留的临时文件
+fn temp_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .contains(TEMP_FILE_MARKER)
+        })
+        .map(|entry| entry.path())
+        .collect())
+}
+
+#[test]
+fn test_failed_write_keeps_previous_output() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.txt");
+    let encrypted = temp_dir.path().join("input.txt.zstd.enc");
+    let restored = temp_dir.path().join("restored.txt");
+    fs::write(&input, b"Hello, World!")?;
+    compress_and_encrypt_mt(&input, &encrypted, &password("secret"), "input")?;
+    let previous = fs::read(&encrypted)?;
+
+    // 读取目录会在写入容器头之后失败
+    let unreadable = temp_dir.path().join("dir");
+    fs::create_dir(&unreadable)?;
+    assert!(
+        compress_and_encrypt_mt(&unreadable, &encrypted, &password("secret"), "input").is_err()
+    );
+    assert_eq!(fs::read(&encrypted)?, previous);
+
+    // 解密失败不覆盖已有的还原文件
+    fs::write(&restored, b"keep me")?;
+    let result = decrypt_and_decompress(&encrypted, &restored, &password("wrong"), "input");
+    assert!(matches!(
+        container_error(result),
+        ContainerError::WrongPassword
+    ));
+    assert_eq!(fs::read(&restored)?, b"keep me");
+
+    // 不遗留临时文件
+    assert!(temp_files(temp_dir.path())?.is_empty());
+
+    // 重命名失败（目标是非空目录）时同样删除临时文件
+    let occupied = temp_dir.path().join("occupied");
+    fs::create_dir_all(occupied.join("child"))?;
+    assert!(write_atomic(&occupied, b"data").is_err());
+    assert!(temp_files(temp_dir.path())?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata_encode_decode() -> Result<()> {
+    let metadata = FileMetadata {
+        unix: Some(UnixOwnership {
+            mode: 0o4755,
+            uid: 1000,
+            gid: 100,
+        }),
+        // 纪元之前且带纳秒的时间
+        modified: Some(UNIX_EPOCH - Duration::new(10, 250)),
+        xattrs: vec![(b"user.tag".to_vec(), b"value".to_vec())],
+    };
+    let encoded = metadata.encode()?;
+    assert_eq!(FileMetadata::decode(&encoded), Ok(metadata));
+
+    assert!(FileMetadata::decode(&encoded[..encoded.len() - 1]).is_err());
+    assert_eq!(
+        FileMetadata::decode(&FileMetadata::default().encode()?),
+        Ok(FileMetadata::default())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata_length_limit() -> Result<()> {
+    // FLAGS + XATTR_COUNT + NAME_LEN + 名称 "a" + VALUE_LEN
+    let overhead = 1 + 2 + 2 + 1 + 4;
+    let with_value_len = |len| FileMetadata {
+        xattrs: vec![(b"a".to_vec(), vec![0u8; len])],
+        ..FileMetadata::default()
+    };
+
+    // 恰好达到上限时可以编码，写出的文件可以还原
+    let metadata = with_value_len(MAX_METADATA_LEN - overhead);
+    let encoded = metadata.encode()?;
+    assert_eq!(encoded.len(), MAX_METADATA_LEN);
+    assert_eq!(FileMetadata::decode(&encoded), Ok(metadata));
+
+    // 超过上限时编码失败，否则写出的文件无法解密
+    assert!(
+        with_value_len(MAX_METADATA_LEN - overhead + 1)
+            .encode()
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_restore_applies_metadata() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input.txt");
+    let encrypted = temp_dir.path().join("input.txt.zstd.enc");
+    let restored = temp_dir.path().join("restored.txt");
+    fs::write(&input, b"Hello, World!")?;
+
+    let modified = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+    fs::set_permissions(&input, fs::Permissions::from_mode(0o640))?;
+    fs::File::options()
+        .write(true)
+        .open(&input)?
+        .set_modified(modified)?;
+    // 部分文件系统不支持 user 命名空间的扩展属性
+    let has_xattr = xattr::set(&input, "user.hbsx-test", b"value").is_ok();
+
+    compress_and_encrypt_mt(&input, &encrypted, &password("secret"), "input")?;
+    decrypt_and_decompress(&encrypted, &restored, &password("secret"), "input")?;
+
+    let metadata = fs::metadata(&restored)?;
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+    assert_eq!(metadata.modified()?, modified);
+    if has_xattr {
+        assert_eq!(
+            xattr::get(&restored, "user.hbsx-test")?,
+            Some(b"value".to_vec())
+        );
+    }
+
+    Ok(())
+}
@@ -1,7 +1,9 @@
 use anyhow::Result;
+use hbsx::db::{Database, FileRecord, LogRecord};
+use hbsx::migrations::{SCHEMA_VERSION, schema_version};
 use rusqlite::Connection;
+use std::path::Path;
 use tempfile::TempDir;
-use xor::db::{Database, FileRecord, LogRecord};
 
 /// 创建临时测试数据库
 /// 返回数据库和 TempDir，以保持临时目录在测试期间有效
@@ -16,6 +18,11 @@ fn create_test_db() -> Result<(Database, TempDir)> {
     Ok((db, temp_dir))
 }
 
+/// 注册测试用的来源
+fn test_source(db: &Database) -> Result<i64> {
+    db.register_source(Path::new("/data/input"), Path::new("/data/output"))
+}
+
 #[test]
 fn test_database_creation() -> Result<()> {
     let (_db, _temp_dir) = create_test_db()?;
@@ -30,6 +37,7 @@ fn test_database_creation() -> Result<()> {
 #[test]
 fn test_file_record_upsert() -> Result<()> {
     let (db, _temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
 
     let record = FileRecord {
         id: None,
@@ -40,13 +48,14 @@ fn test_file_record_upsert() -> Result<()> {
         original_size: 1024,
         output_size: 512,
         created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
     };
 
     // 插入记录
-    db.upsert_file(&record)?;
+    db.upsert_file(source_id, &record)?;
 
     // 验证记录存在
-    let found = db.file_exists("test/file.txt")?;
+    let found = db.file_exists(source_id, "test/file.txt")?;
     assert!(found.is_some());
 
     let found_record = found.unwrap();
@@ -60,6 +69,7 @@ fn test_file_record_upsert() -> Result<()> {
 #[test]
 fn test_file_record_update() -> Result<()> {
     let (db, _temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
 
     // 插入初始记录
     let record1 = FileRecord {
@@ -71,8 +81,9 @@ fn test_file_record_update() -> Result<()> {
         original_size: 1024,
         output_size: 512,
         created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
     };
-    db.upsert_file(&record1)?;
+    db.upsert_file(source_id, &record1)?;
 
     // 更新记录
     let record2 = FileRecord {
@@ -84,11 +95,12 @@ fn test_file_record_update() -> Result<()> {
         original_size: 2048,
         output_size: 1024,
         created_at: "2025-12-10 11:00:00".to_string(),
+        object_id: None,
     };
-    db.upsert_file(&record2)?;
+    db.upsert_file(source_id, &record2)?;
 
     // 验证更新成功
-    let found = db.file_exists("test/file.txt")?;
+    let found = db.file_exists(source_id, "test/file.txt")?;
     assert!(found.is_some());
 
     let found_record = found.unwrap();
@@ -101,6 +113,7 @@ fn test_file_record_update() -> Result<()> {
 #[test]
 fn test_batch_upsert_files() -> Result<()> {
     let (mut db, _temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
 
     let records = vec![
         FileRecord {
@@ -112,6 +125,7 @@ fn test_batch_upsert_files() -> Result<()> {
             original_size: 100,
             output_size: 50,
             created_at: "2025-12-10 10:00:00".to_string(),
+            object_id: None,
         },
         FileRecord {
             id: None,
@@ -122,6 +136,7 @@ fn test_batch_upsert_files() -> Result<()> {
             original_size: 200,
             output_size: 100,
             created_at: "2025-12-10 10:00:00".to_string(),
+            object_id: None,
         },
         FileRecord {
             id: None,
@@ -132,15 +147,16 @@ fn test_batch_upsert_files() -> Result<()> {
             original_size: 300,
             output_size: 150,
             created_at: "2025-12-10 10:00:00".to_string(),
+            object_id: None,
         },
     ];
 
     // 批量插入
-    db.batch_upsert_files(&records)?;
+    db.batch_upsert_files(source_id, &records)?;
 
     // 验证所有记录
     for record in &records {
-        let found = db.file_exists(&record.relative_path)?;
+        let found = db.file_exists(source_id, &record.relative_path)?;
         assert!(found.is_some());
         assert_eq!(found.unwrap().original_hash, record.original_hash);
     }
@@ -213,6 +229,7 @@ fn test_batch_add_logs() -> Result<()> {
 #[test]
 fn test_get_all_files() -> Result<()> {
     let (mut db, _temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
 
     // 插入多个文件记录
     let records = vec![
@@ -225,6 +242,7 @@ fn test_get_all_files() -> Result<()> {
             original_size: 100,
             output_size: 50,
             created_at: "2025-12-10 10:00:00".to_string(),
+            object_id: None,
         },
         FileRecord {
             id: None,
@@ -235,13 +253,14 @@ fn test_get_all_files() -> Result<()> {
             original_size: 200,
             output_size: 100,
             created_at: "2025-12-10 10:00:00".to_string(),
+            object_id: None,
         },
     ];
 
-    db.batch_upsert_files(&records)?;
+    db.batch_upsert_files(source_id, &records)?;
 
     // 获取所有文件
-    let all_files = db.get_all_files()?;
+    let all_files = db.get_all_files(source_id)?;
     assert_eq!(all_files.len(), 2);
 
     // 验证按路径排序
@@ -254,9 +273,10 @@ fn test_get_all_files() -> Result<()> {
 #[test]
 fn test_file_not_exists() -> Result<()> {
     let (db, _temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
 
     // 查询不存在的文件
-    let found = db.file_exists("nonexistent.txt")?;
+    let found = db.file_exists(source_id, "nonexistent.txt")?;
     assert!(found.is_none());
 
     Ok(())
@@ -265,10 +285,330 @@ fn test_file_not_exists() -> Result<()> {
 #[test]
 fn test_empty_batch_operations() -> Result<()> {
     let (mut db, _temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
 
     // 空批量操作应该成功
-    db.batch_upsert_files(&[])?;
+    db.batch_upsert_files(source_id, &[])?;
     db.batch_add_logs(&[])?;
 
     Ok(())
 }
+
+#[test]
+fn test_logs_filter_and_prune() -> Result<()> {
+    let (mut db, _temp_dir) = create_test_db()?;
+
+    let logs = vec![
+        LogRecord {
+            file_path: "old.txt".to_string(),
+            action: "process".to_string(),
+            status: "failed".to_string(),
+            message: "Error occurred".to_string(),
+            timestamp: "2025-01-01 10:00:00".to_string(),
+        },
+        LogRecord {
+            file_path: "new.txt".to_string(),
+            action: "process".to_string(),
+            status: "success".to_string(),
+            message: "Processed".to_string(),
+            timestamp: "2025-12-10 10:00:00".to_string(),
+        },
+    ];
+    db.batch_add_logs(&logs)?;
+
+    // 按状态过滤
+    let failed = db.get_recent_logs_by_status(10, Some("failed"))?;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].file_path, "old.txt");
+
+    // 删除旧日志
+    let deleted = db.prune_logs("2025-06-01 00:00:00")?;
+    assert_eq!(deleted, 1);
+
+    let remaining = db.get_recent_logs(10)?;
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].file_path, "new.txt");
+
+    Ok(())
+}
+
+#[test]
+fn test_mark_deleted() -> Result<()> {
+    let (mut db, _temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
+
+    let record = FileRecord {
+        id: None,
+        relative_path: "gone.txt".to_string(),
+        modified_time: "2025-12-10 10:00:00".to_string(),
+        original_hash: "abc123".to_string(),
+        output_hash: "def456".to_string(),
+        original_size: 1024,
+        output_size: 512,
+        created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
+    };
+    db.upsert_file(source_id, &record)?;
+
+    // 标记删除后不再出现在文件列表中
+    assert_eq!(db.mark_deleted(source_id, &["gone.txt".to_string()])?, 1);
+    assert!(db.file_exists(source_id, "gone.txt")?.is_none());
+    assert!(db.get_all_files(source_id)?.is_empty());
+    assert_eq!(db.get_deleted_files(source_id)?.len(), 1);
+
+    // 重复标记不生效
+    assert_eq!(db.mark_deleted(source_id, &["gone.txt".to_string()])?, 0);
+
+    // 重新写入时清除删除标记
+    db.upsert_file(source_id, &record)?;
+    assert!(db.file_exists(source_id, "gone.txt")?.is_some());
+    assert!(db.get_deleted_files(source_id)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_sources_isolate_files() -> Result<()> {
+    let (db, _temp_dir) = create_test_db()?;
+    let a = db.register_source(Path::new("/project-a"), Path::new("/backup/a"))?;
+    let b = db.register_source(Path::new("/project-b"), Path::new("/backup/b"))?;
+    assert_ne!(a, b);
+
+    // 相同的目录对返回同一个来源
+    assert_eq!(
+        db.register_source(Path::new("/project-a"), Path::new("/backup/a"))?,
+        a
+    );
+    assert_eq!(db.get_sources()?.len(), 2);
+    assert_eq!(db.find_sources_by_output(Path::new("/backup/b"))?[0].id, b);
+
+    // 目录路径无损保存，有损转换后相同的两个目录仍是不同的来源
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let first = Path::new(OsStr::from_bytes(b"/caf\xe9"));
+        let second = Path::new(OsStr::from_bytes(b"/caf\xe8"));
+        let c = db.register_source(first, Path::new("/backup/c"))?;
+        let d = db.register_source(second, Path::new("/backup/c"))?;
+        assert_ne!(c, d);
+        assert_eq!(
+            db.find_source(second, Path::new("/backup/c"))?.unwrap().id,
+            d
+        );
+        assert_eq!(db.find_sources_by_output(Path::new("/backup/c"))?.len(), 2);
+    }
+
+    // 两个来源中相同的相对路径互不影响
+    let record = FileRecord {
+        id: None,
+        relative_path: "README.md".to_string(),
+        modified_time: "2025-12-10 10:00:00".to_string(),
+        original_hash: "aaa".to_string(),
+        output_hash: "bbb".to_string(),
+        original_size: 10,
+        output_size: 5,
+        created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
+    };
+    db.upsert_file(a, &record)?;
+    db.upsert_file(
+        b,
+        &FileRecord {
+            original_hash: "ccc".to_string(),
+            ..record.clone()
+        },
+    )?;
+
+    assert_eq!(
+        db.file_exists(a, "README.md")?.unwrap().original_hash,
+        "aaa"
+    );
+    assert_eq!(
+        db.file_exists(b, "README.md")?.unwrap().original_hash,
+        "ccc"
+    );
+    assert_eq!(db.get_all_files(a)?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_migrate_legacy_files_table() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("legacy.db");
+
+    // 旧版本的表结构：relative_path 全局唯一，没有 source_id
+    let conn = Connection::open(&db_path)?;
+    conn.execute_batch(
+        "CREATE TABLE files (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            relative_path TEXT NOT NULL UNIQUE,
+            modified_time TEXT NOT NULL,
+            original_hash TEXT NOT NULL,
+            output_hash TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+        INSERT INTO files (relative_path, modified_time, original_hash, output_hash, created_at, updated_at)
+        VALUES ('old.txt', 't', 'h1', 'h2', 't', 't');",
+    )?;
+    drop(conn);
+
+    let db = Database::open(&db_path)?;
+    assert_eq!(db.count_unsourced_files()?, 1);
+
+    // 重建后不同来源可以使用相同的相对路径
+    let source_id = test_source(&db)?;
+    let record = FileRecord {
+        id: None,
+        relative_path: "old.txt".to_string(),
+        modified_time: "t".to_string(),
+        original_hash: "h1".to_string(),
+        output_hash: "h2".to_string(),
+        original_size: 0,
+        output_size: 0,
+        created_at: "t".to_string(),
+        object_id: None,
+    };
+    db.upsert_file(source_id, &record)?;
+    assert!(db.file_exists(source_id, "old.txt")?.is_some());
+    assert_eq!(db.count_unsourced_files()?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_schema_version_is_recorded() -> Result<()> {
+    let (db, _temp_dir) = create_test_db()?;
+    assert_eq!(schema_version(&db.conn)?, SCHEMA_VERSION);
+
+    // 重复执行迁移不产生变化
+    db.init_tables()?;
+    assert_eq!(schema_version(&db.conn)?, SCHEMA_VERSION);
+
+    Ok(())
+}
+
+#[test]
+fn test_migrations_skip_existing_columns() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("legacy.db");
+
+    // 版本号未记录、但已经有 deleted_at 列的旧表
+    let conn = Connection::open(&db_path)?;
+    conn.execute_batch(
+        "CREATE TABLE files (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            relative_path TEXT NOT NULL UNIQUE,
+            modified_time TEXT NOT NULL,
+            original_hash TEXT NOT NULL,
+            output_hash TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            deleted_at TEXT
+        );",
+    )?;
+    drop(conn);
+
+    let db = Database::open(&db_path)?;
+    assert_eq!(schema_version(&db.conn)?, SCHEMA_VERSION);
+
+    Ok(())
+}
+
+#[test]
+fn test_newer_database_is_rejected() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("future.db");
+
+    let conn = Connection::open(&db_path)?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+    drop(conn);
+
+    let error = Database::open(&db_path).err().unwrap();
+    assert!(error.to_string().contains("请升级"));
+
+    Ok(())
+}
+
+#[test]
+fn test_path_encoding_roundtrip() {
+    use hbsx::pathenc::{decode_path, decode_relative_path, encode_path};
+
+    let path = Path::new("docs").join("100% done").join("a b.txt");
+    assert_eq!(encode_path(&path), "docs/100%25 done/a b.txt");
+    assert_eq!(decode_path(&encode_path(&path)), path);
+    assert_eq!(encode_path(Path::new("/usr/lib")), "/usr/lib");
+    assert_eq!(decode_path("/usr/lib"), Path::new("/usr/lib"));
+
+    // 拼接到还原目录下的路径不能越出该目录
+    assert_eq!(
+        decode_relative_path("docs/a.txt").unwrap(),
+        Path::new("docs/a.txt")
+    );
+    for escaping in [
+        "../a.txt",
+        "docs/../../a.txt",
+        "/etc/passwd",
+        "..%2Fa.txt",
+        "",
+    ] {
+        assert!(decode_relative_path(escaping).is_err());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"caf\xe9\\x.txt"));
+        assert_eq!(encode_path(path), "caf%E9%5Cx.txt");
+        assert_eq!(decode_path(&encode_path(path)), path);
+    }
+}
+
+#[test]
+fn test_migrate_escapes_legacy_paths() -> Result<()> {
+    let (db, temp_dir) = create_test_db()?;
+    let source_id = test_source(&db)?;
+
+    // 模拟迁移前按原样保存的路径
+    for path in ["100%.txt", "plain.txt", "a\\b.txt"] {
+        db.conn.execute(
+            "INSERT INTO files (source_id, relative_path, modified_time, original_hash,
+                                output_hash, created_at, updated_at)
+             VALUES (?1, ?2, 't', 'h1', 'h2', 't', 't')",
+            rusqlite::params![source_id, path],
+        )?;
+    }
+    db.conn.execute(
+        "INSERT INTO sources (input_root, output_root, created_at)
+         VALUES ('/data/100%', '/backup/100%', 't')",
+        [],
+    )?;
+    db.conn.pragma_update(None, "user_version", 5)?;
+    drop(db);
+
+    let db = Database::open(&temp_dir.path().join("test.db"))?;
+    assert_eq!(schema_version(&db.conn)?, SCHEMA_VERSION);
+    let source = db.find_source(Path::new("/data/100%"), Path::new("/backup/100%"))?;
+    assert_eq!(source.unwrap().input_root, "/data/100%25");
+    let mut paths: Vec<String> = db
+        .get_all_files(source_id)?
+        .into_iter()
+        .map(|record| record.relative_path)
+        .collect();
+    paths.sort();
+    let backslash = if cfg!(windows) {
+        "a/b.txt"
+    } else {
+        "a%5Cb.txt"
+    };
+    let mut expected = vec!["100%25.txt", "plain.txt", backslash];
+    expected.sort();
+    assert_eq!(paths, expected);
+
+    Ok(())
+}
@@ -0,0 +1,1192 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit},
+};
+use anyhow::Result;
+use hbsx::container::{ContainerError, MAGIC, NONCE_LEN};
+use hbsx::db::{Database, EntryKind, EntryRecord};
+use hbsx::engine::{
+    CollisionError, EncryptOptions, FileEvent, FileStatus, FlushPolicy, OrphanPolicy,
+    PlannedAction, RestoreFailureKind, RestoreOptions, VerifyMode, VerifyOptions, encrypt_tree,
+    plan_tree, restore_tree, verify_tree,
+};
+use hbsx::fsutil::{compute_file_hash_simd, get_modified_time};
+use hbsx::keys::{Kdf, KeyRing, MasterKey, PBKDF2_ITERS};
+use hbsx::manifest::ManifestFormat;
+use hbsx::names::{load_index, object_relative_path};
+use hbsx::walk::{FileFilter, SymlinkPolicy};
+use pbkdf2::pbkdf2_hmac;
+use rusqlite::{Connection, params};
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+/// 创建包含几个测试文件的输入目录
+fn create_input(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir.join("sub"))?;
+    fs::write(dir.join("a.txt"), b"Hello, World!")?;
+    fs::write(dir.join("sub").join("b.bin"), vec![7u8; 10_000])?;
+    Ok(())
+}
+
+fn encrypt_options(temp_dir: &TempDir) -> EncryptOptions {
+    EncryptOptions {
+        input: temp_dir.path().join("input"),
+        output: temp_dir.path().join("output"),
+        password: "secret".to_string(),
+        kdf: Some(Kdf::Argon2id {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        }),
+        orphans: OrphanPolicy::Keep,
+        flush: FlushPolicy::default(),
+        filter: FileFilter::default(),
+        symlinks: SymlinkPolicy::Skip,
+        encrypt_names: false,
+        cancel: None,
+        manifest_formats: vec![ManifestFormat::Csv],
+        delta_manifest: false,
+    }
+}
+
+fn fast_verify(output: &Path) -> VerifyOptions {
+    VerifyOptions {
+        output: output.to_path_buf(),
+        mode: VerifyMode::Fast,
+    }
+}
+
+#[test]
+fn test_encrypt_tree_is_incremental() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert_eq!(report.total_files, 2);
+    assert_eq!(report.processed.len(), 2);
+    assert!(report.failed.is_empty());
+    assert!(report.processed.iter().all(|p| p.status == FileStatus::New));
+    assert!(report.manifest_paths.iter().all(|path| path.exists()));
+
+    // 第二次运行没有变化的文件应被跳过
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(report.processed.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_tree_roundtrip() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    let restore_options = RestoreOptions {
+        input: options.output.clone(),
+        output: temp_dir.path().join("restored"),
+        password: "secret".to_string(),
+    };
+    let report = restore_tree(&db, &restore_options)?;
+    assert_eq!(report.restored.len(), 2);
+    assert!(report.failed.is_empty());
+    assert_eq!(
+        fs::read(restore_options.output.join("a.txt"))?,
+        b"Hello, World!"
+    );
+
+    // 错误的密码在读取密钥文件时即被拒绝
+    let error = restore_tree(
+        &db,
+        &RestoreOptions {
+            password: "wrong".to_string(),
+            ..restore_options
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<ContainerError>(),
+        Some(ContainerError::WrongPassword)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_tree() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    let report = verify_tree(&mut db, &fast_verify(&options.output))?;
+    assert_eq!(report.checked, 2);
+    assert!(report.mismatched.is_empty());
+
+    fs::remove_file(options.output.join("a.txt.zstd.enc"))?;
+    let report = verify_tree(&mut db, &fast_verify(&options.output))?;
+    assert_eq!(report.mismatched.len(), 1);
+    assert_eq!(report.mismatched[0].relative_path, "a.txt");
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_detects_swapped_files() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+    fs::write(options.input.join("c.txt"), b"Another file")?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    // 互换两个加密文件
+    let a = options.output.join("a.txt.zstd.enc");
+    let c = options.output.join("c.txt.zstd.enc");
+    let tmp = options.output.join("tmp");
+    fs::rename(&a, &tmp)?;
+    fs::rename(&c, &a)?;
+    fs::rename(&tmp, &c)?;
+
+    let report = restore_tree(
+        &db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: temp_dir.path().join("restored"),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert_eq!(report.restored.len(), 1);
+    assert_eq!(report.failed.len(), 2);
+    assert!(
+        report
+            .failed
+            .iter()
+            .all(|f| f.kind == RestoreFailureKind::Corrupted)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_deleted_files_are_tombstoned() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let mut options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+    fs::write(options.input.join("c.txt"), b"Another file")?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+    let source_id = db.find_sources_by_output(&fs::canonicalize(&options.output)?)?[0].id;
+
+    // 保留策略：输出文件仍在，记录被标记删除
+    fs::remove_file(options.input.join("a.txt"))?;
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert_eq!(report.deleted.len(), 1);
+    assert_eq!(report.deleted[0].relative_path, "a.txt");
+    assert!(options.output.join("a.txt.zstd.enc").exists());
+    assert!(db.file_exists(source_id, "a.txt")?.is_none());
+    assert_eq!(db.get_deleted_files(source_id)?.len(), 1);
+
+    // 已标记的记录不会重复处理
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(report.deleted.is_empty());
+
+    // 删除策略
+    options.orphans = OrphanPolicy::Remove;
+    fs::remove_file(options.input.join("c.txt"))?;
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert_eq!(report.deleted.len(), 1);
+    assert!(!options.output.join("c.txt.zstd.enc").exists());
+
+    // 归档策略：保持原相对路径
+    options.orphans = OrphanPolicy::Archive;
+    fs::remove_dir_all(options.input.join("sub"))?;
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    let archived = report.deleted[0].output.clone().unwrap();
+    assert!(archived.ends_with("sub/b.bin.zstd.enc"));
+    assert!(archived.exists());
+    assert!(!options.output.join("sub").join("b.bin.zstd.enc").exists());
+
+    // 重新出现的文件作为新文件处理
+    fs::write(options.input.join("a.txt"), b"Hello again")?;
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert_eq!(report.processed.len(), 1);
+    assert_eq!(report.processed[0].status, FileStatus::New);
+    assert!(db.file_exists(source_id, "a.txt")?.is_some());
+    assert_eq!(db.get_all_files(source_id)?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_sources_do_not_collide() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let first = encrypt_options(&temp_dir);
+    create_input(&first.input)?;
+    let second = EncryptOptions {
+        input: temp_dir.path().join("input2"),
+        output: temp_dir.path().join("output2"),
+        ..first.clone()
+    };
+    create_input(&second.input)?;
+
+    // 两个输入目录中有相同的相对路径，互不影响
+    assert_eq!(encrypt_tree(&mut db, &first, |_| {})?.processed.len(), 2);
+    assert_eq!(encrypt_tree(&mut db, &second, |_| {})?.processed.len(), 2);
+    assert!(encrypt_tree(&mut db, &first, |_| {})?.processed.is_empty());
+    assert_eq!(db.get_sources()?.len(), 2);
+
+    let report = verify_tree(&mut db, &fast_verify(&second.output))?;
+    assert_eq!(report.checked, 2);
+    assert!(report.mismatched.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_output_names_keep_extension() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    fs::create_dir_all(&options.input)?;
+    fs::write(options.input.join("notes.txt"), b"text")?;
+    fs::write(options.input.join("notes.md"), b"markdown")?;
+
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert_eq!(report.processed.len(), 2);
+    assert!(options.output.join("notes.txt.zstd.enc").exists());
+    assert!(options.output.join("notes.md.zstd.enc").exists());
+
+    let restored = temp_dir.path().join("restored");
+    restore_tree(
+        &db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: restored.clone(),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert_eq!(fs::read(restored.join("notes.txt"))?, b"text");
+    assert_eq!(fs::read(restored.join("notes.md"))?, b"markdown");
+
+    Ok(())
+}
+
+#[test]
+fn test_output_collisions_abort_before_processing() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+
+    // 文件 x 的输出 x.zstd.enc 与目录 x.zstd.enc 冲突；目录 .archive 占用了保留名称
+    fs::create_dir_all(options.input.join("x.zstd.enc"))?;
+    fs::create_dir_all(options.input.join(".archive"))?;
+    fs::write(options.input.join("x"), b"file")?;
+    fs::write(options.input.join("x.zstd.enc").join("y"), b"nested")?;
+    fs::write(options.input.join(".archive").join("z"), b"reserved")?;
+    fs::write(options.input.join("ok.txt"), b"fine")?;
+
+    let error = encrypt_tree(&mut db, &options, |_| {}).unwrap_err();
+    let collisions = &error.downcast_ref::<CollisionError>().unwrap().0;
+    assert_eq!(collisions.len(), 2);
+    assert_eq!(collisions[0].inputs, vec![".archive/z"]);
+    assert_eq!(collisions[1].output, "x.zstd.enc");
+    assert_eq!(collisions[1].inputs, vec!["x", "x.zstd.enc/y"]);
+
+    // 没有处理任何文件
+    assert!(!options.output.join("ok.txt.zstd.enc").exists());
+    assert!(db.get_sources()?.is_empty());
+
+    Ok(())
+}
+
+/// 按升级前的版本生成输出目录和数据库：旧的表结构（没有来源）、旧的输出文件命名
+/// （替换原扩展名）和 VERSION 1 容器格式
+fn create_baseline_output(input: &Path, output: &Path, db_path: &Path) -> Result<()> {
+    let conn = Connection::open(db_path)?;
+    conn.execute_batch(
+        "CREATE TABLE files (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            relative_path TEXT NOT NULL UNIQUE,
+            modified_time TEXT NOT NULL,
+            original_hash TEXT NOT NULL,
+            output_hash TEXT NOT NULL,
+            original_size INTEGER NOT NULL DEFAULT 0,
+            output_size INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+        CREATE TABLE logs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_path TEXT NOT NULL,
+            action TEXT NOT NULL,
+            status TEXT NOT NULL,
+            message TEXT,
+            timestamp TEXT NOT NULL
+        );",
+    )?;
+    for relative_path in ["a.txt", "sub/b.bin"] {
+        let file_path = input.join(relative_path);
+        let output_file = output.join(Path::new(relative_path).with_extension("zstd.enc"));
+        write_version_1(&output_file, &fs::read(&file_path)?)?;
+        // 旧版本的修改时间只精确到秒
+        let modified_time = &get_modified_time(&file_path)?[.."YYYY-MM-DD HH:MM:SS".len()];
+        conn.execute(
+            "INSERT INTO files (relative_path, modified_time, original_hash, output_hash,
+                                original_size, output_size, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?2, ?2)",
+            params![
+                relative_path,
+                modified_time,
+                compute_file_hash_simd(&file_path)?,
+                compute_file_hash_simd(&output_file)?,
+                fs::metadata(&file_path)?.len(),
+                fs::metadata(&output_file)?.len(),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// 用密码 `secret` 写入 VERSION 1 格式的加密文件
+fn write_version_1(path: &Path, content: &[u8]) -> Result<()> {
+    let salt = [1u8; 16];
+    let nonce = [2u8; NONCE_LEN];
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(b"secret", &salt, PBKDF2_ITERS, &mut key);
+    let compressed = zstd::stream::encode_all(content, 3)?;
+    let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), compressed.as_ref())
+        .unwrap();
+
+    let mut data = MAGIC.to_vec();
+    data.push(1);
+    data.push(salt.len() as u8);
+    data.extend_from_slice(&salt);
+    data.push(NONCE_LEN as u8);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, data)?;
+    Ok(())
+}
+
+#[test]
+fn test_legacy_output_name_is_migrated() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let options = encrypt_options(&temp_dir);
+    let db_path = temp_dir.path().join("test.db");
+    create_input(&options.input)?;
+    create_baseline_output(&options.input, &options.output, &db_path)?;
+    let mut db = Database::open(&db_path)?;
+
+    // 再次加密时旧命名的文件改为新名称
+    encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(options.output.join("a.txt.zstd.enc").exists());
+    assert!(options.output.join("sub").join("b.bin.zstd.enc").exists());
+    assert!(!options.output.join("a.zstd.enc").exists());
+    assert!(!options.output.join("sub").join("b.zstd.enc").exists());
+
+    // 新旧命名的文件同时存在时只还原新文件，旧文件中的过期内容不会覆盖
+    write_version_1(&options.output.join("a.zstd.enc"), b"stale")?;
+    let restored = temp_dir.path().join("restored");
+    let report = restore_tree(
+        &db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: restored.clone(),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert!(report.failed.is_empty());
+    assert_eq!(report.restored.len(), 2);
+    assert_eq!(fs::read(restored.join("a.txt"))?, b"Hello, World!");
+    assert_eq!(
+        fs::read(restored.join("sub").join("b.bin"))?,
+        vec![7u8; 10_000]
+    );
+
+    // 与记录不一致的旧文件不与其他输入冲突时，在重新生成时一并删除
+    fs::write(options.input.join("a.txt"), b"changed")?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(!options.output.join("a.zstd.enc").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_baseline_catalog_is_adopted() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let options = encrypt_options(&temp_dir);
+    let db_path = temp_dir.path().join("test.db");
+    create_input(&options.input)?;
+    create_baseline_output(&options.input, &options.output, &db_path)?;
+    let mut db = Database::open(&db_path)?;
+    assert_eq!(db.count_unsourced_files()?, 2);
+
+    // 升级后的首次运行沿用旧记录，没有需要处理的文件
+    let plan = plan_tree(&db, &options)?;
+    assert!(
+        plan.files
+            .iter()
+            .all(|file| file.action == PlannedAction::Unchanged)
+    );
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(report.processed.is_empty());
+    assert!(report.deleted.is_empty());
+    assert_eq!(db.count_unsourced_files()?, 0);
+    assert_eq!(db.get_sources()?.len(), 1);
+    assert!(
+        verify_tree(&mut db, &fast_verify(&options.output))?
+            .mismatched
+            .is_empty()
+    );
+
+    // 其他输出目录不会接管这些记录
+    let other = EncryptOptions {
+        output: temp_dir.path().join("other"),
+        ..options.clone()
+    };
+    let report = encrypt_tree(&mut db, &other, |_| {})?;
+    assert_eq!(report.processed.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_deep_verify_decrypts_outputs() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    let deep = VerifyOptions {
+        output: options.output.clone(),
+        mode: VerifyMode::Deep {
+            password: "secret".to_string(),
+        },
+    };
+    let report = verify_tree(&mut db, &deep)?;
+    assert_eq!(report.checked, 2);
+    assert!(report.mismatched.is_empty());
+
+    // 篡改密文
+    let encrypted = options.output.join("sub").join("b.bin.zstd.enc");
+    let mut data = fs::read(&encrypted)?;
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    fs::write(&encrypted, data)?;
+
+    let report = verify_tree(&mut db, &deep)?;
+    assert_eq!(report.mismatched.len(), 1);
+    assert!(report.mismatched[0].reason.starts_with("解密失败"));
+
+    // 错误的密码直接返回错误
+    let wrong = VerifyOptions {
+        mode: VerifyMode::Deep {
+            password: "wrong".to_string(),
+        },
+        ..deep
+    };
+    assert!(verify_tree(&mut db, &wrong).is_err());
+
+    // 结果写入日志
+    let logs = db.get_recent_logs_by_status(10, Some("mismatch"))?;
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].action, "verify");
+    assert_eq!(logs[0].file_path, "sub/b.bin");
+
+    Ok(())
+}
+
+#[test]
+fn test_records_are_flushed_during_run() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("test.db");
+    let mut db = Database::open(&db_path)?;
+    let options = EncryptOptions {
+        flush: FlushPolicy {
+            batch_size: 1,
+            interval: Duration::from_secs(3600),
+        },
+        ..encrypt_options(&temp_dir)
+    };
+    create_input(&options.input)?;
+
+    // 每个文件处理完成时，其记录已经可以从另一个连接读到
+    let persisted = Mutex::new(Vec::new());
+    encrypt_tree(&mut db, &options, |event| {
+        if let FileEvent::Processed(file) = event {
+            let reader = Database::open(&db_path).unwrap();
+            let source = &reader.get_sources().unwrap()[0];
+            let found = reader
+                .file_exists(source.id, &file.record.relative_path)
+                .unwrap()
+                .is_some();
+            persisted.lock().unwrap().push(found);
+        }
+    })?;
+    assert_eq!(*persisted.lock().unwrap(), vec![true, true]);
+
+    Ok(())
+}
+
+#[test]
+fn test_cancelled_run_keeps_catalog() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    // 取消后不处理新文件，也不把未处理的文件当作已删除
+    fs::write(options.input.join("c.txt"), b"new")?;
+    let cancel = Arc::new(AtomicBool::new(true));
+    let cancelled = EncryptOptions {
+        cancel: Some(cancel.clone()),
+        ..options.clone()
+    };
+    let report = encrypt_tree(&mut db, &cancelled, |_| {})?;
+    assert!(report.interrupted);
+    assert!(report.processed.is_empty());
+    assert!(report.deleted.is_empty());
+
+    // 再次运行从中断处继续
+    cancel.store(false, Ordering::SeqCst);
+    let report = encrypt_tree(&mut db, &cancelled, |_| {})?;
+    assert!(!report.interrupted);
+    assert_eq!(report.processed.len(), 1);
+    assert_eq!(report.processed[0].record.relative_path, "c.txt");
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest_covers_full_catalog() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = EncryptOptions {
+        delta_manifest: true,
+        ..encrypt_options(&temp_dir)
+    };
+    create_input(&options.input)?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    // 没有变化的增量运行仍然生成完整清单
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(report.processed.is_empty());
+    let manifest = fs::read_to_string(&report.manifest_paths[0])?;
+    assert_eq!(manifest.lines().count(), 3);
+    assert!(manifest.contains("a.txt"));
+
+    let delta_path = report.delta_manifest_paths[0].clone();
+    assert_eq!(fs::read_to_string(&delta_path)?.lines().count(), 1);
+
+    // 变更清单只包含本次新增、更新和删除的文件
+    let a_path = options.input.join("a.txt");
+    fs::write(&a_path, b"changed")?;
+    // 修改时间精确到秒，手动推后以确保被识别为变化
+    fs::File::options()
+        .write(true)
+        .open(&a_path)?
+        .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+    fs::remove_file(options.input.join("sub").join("b.bin"))?;
+    fs::write(options.input.join("c.txt"), b"new")?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    let delta = fs::read_to_string(&delta_path)?;
+    let mut changes: Vec<(&str, &str)> = delta
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split(',').next().zip(line.split(',').nth(1)))
+        .collect();
+    changes.sort();
+    assert_eq!(
+        changes,
+        vec![("删除", "sub/b.bin"), ("新增", "c.txt"), ("更新", "a.txt"),]
+    );
+    let manifest = fs::read_to_string(options.output.join("manifest.csv"))?;
+    assert_eq!(manifest.lines().count(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_json_manifests_include_summary() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = EncryptOptions {
+        manifest_formats: vec![ManifestFormat::Json, ManifestFormat::Ndjson],
+        delta_manifest: true,
+        ..encrypt_options(&temp_dir)
+    };
+    create_input(&options.input)?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+    fs::write(options.input.join("c.txt"), b"new")?;
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+
+    assert_eq!(report.summary.total_files, 3);
+    assert_eq!(report.summary.new, 1);
+    assert_eq!(report.summary.skipped, 2);
+    assert_eq!(report.summary.original_bytes, 3);
+
+    // JSON 清单包含所有文件记录和运行摘要
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(options.output.join("manifest.json"))?)?;
+    assert_eq!(json["files"].as_array().unwrap().len(), 3);
+    assert!(json["files"][0]["original_hash"].is_string());
+    assert_eq!(json["summary"]["new"], 1);
+    assert_eq!(json["summary"]["skipped"], 2);
+
+    // NDJSON 每行一个文件，最后一行为摘要
+    let ndjson = fs::read_to_string(options.output.join("manifest.ndjson"))?;
+    let lines: Vec<serde_json::Value> = ndjson
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(lines.len(), 4);
+    assert!(lines[..3].iter().all(|line| line["type"] == "file"));
+    assert_eq!(lines[3]["type"], "summary");
+
+    // 变更清单只包含新文件
+    let delta: serde_json::Value = serde_json::from_str(&fs::read_to_string(
+        options.output.join("manifest-delta.json"),
+    )?)?;
+    assert_eq!(delta["files"].as_array().unwrap().len(), 1);
+    assert_eq!(delta["files"][0]["change"], "new");
+    assert_eq!(delta["files"][0]["relative_path"], "c.txt");
+    assert!(!options.output.join("manifest.csv").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_ignore_rules_and_filters() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let input = temp_dir.path().join("input");
+    let db_path = input.join("catalog.sqlite");
+    let mut db = Database::open(&db_path)?;
+    let options = EncryptOptions {
+        // 输出目录位于输入目录中
+        output: input.join("backup"),
+        filter: FileFilter {
+            exclude: vec!["*.log".to_string()],
+            ..FileFilter::default()
+        },
+        ..encrypt_options(&temp_dir)
+    };
+    create_input(&input)?;
+    fs::create_dir_all(input.join("target").join("debug"))?;
+    fs::write(input.join("target").join("debug").join("app"), b"binary")?;
+    fs::write(input.join("sub").join("a.swp"), b"swap")?;
+    fs::write(input.join("debug.log"), b"log")?;
+    fs::write(input.join(".xorignore"), "target/\n")?;
+    fs::write(input.join("sub").join(".xorignore"), "*.swp\n")?;
+
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    let mut paths: Vec<String> = report
+        .processed
+        .iter()
+        .map(|p| p.record.relative_path.clone())
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![".xorignore", "a.txt", "sub/.xorignore", "sub/b.bin"]
+    );
+
+    // 再次运行时输出目录和数据库不会被当作输入
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(report.processed.is_empty());
+    assert!(report.failed.is_empty());
+
+    // 大小过滤跳过的文件不视为删除
+    fs::write(input.join("large.bin"), vec![1u8; 4096])?;
+    let filtered = EncryptOptions {
+        filter: FileFilter {
+            max_size: Some(1024),
+            ..options.filter.clone()
+        },
+        ..options.clone()
+    };
+    let report = encrypt_tree(&mut db, &filtered, |_| {})?;
+    assert!(report.processed.is_empty());
+    assert!(report.deleted.is_empty());
+    assert!(report.summary.filtered >= 2);
+
+    // 新增的排除规则使已加密的文件视为删除
+    fs::write(input.join(".xorignore"), "target/\n*.bin\n")?;
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    let deleted: Vec<String> = report
+        .deleted
+        .iter()
+        .map(|d| d.relative_path.clone())
+        .collect();
+    assert_eq!(deleted, vec!["sub/b.bin"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_filter_values() {
+    use hbsx::walk::{parse_duration, parse_size};
+
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("64K"), Ok(64 * 1024));
+    assert_eq!(parse_size("10MiB"), Ok(10 * 1024 * 1024));
+    assert!(parse_size("10X").is_err());
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+    assert!(parse_duration("1w").is_err());
+}
+
+#[test]
+fn test_plan_matches_encrypt_without_writing() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    create_input(&options.input)?;
+
+    // 首次预演：所有文件都是新文件，不创建输出目录
+    let plan = plan_tree(&db, &options)?;
+    assert_eq!(plan.counts.get(&PlannedAction::New), Some(&2));
+    assert_eq!(plan.bytes_to_process, 13 + 10_000);
+    assert!(!options.output.exists());
+
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    fs::write(options.input.join("c.txt"), b"new")?;
+    fs::remove_file(options.input.join("sub").join("b.bin"))?;
+    // 已有输出目录时也不写入任何文件（包括检测大小写敏感的探测文件）
+    let output_modified = fs::metadata(&options.output)?.modified()?;
+    let plan = plan_tree(&db, &options)?;
+    assert_eq!(fs::metadata(&options.output)?.modified()?, output_modified);
+    let actions: Vec<(&str, PlannedAction)> = plan
+        .files
+        .iter()
+        .map(|f| (f.relative_path.as_str(), f.action))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            ("a.txt", PlannedAction::Unchanged),
+            ("c.txt", PlannedAction::New),
+            ("sub/b.bin", PlannedAction::Delete),
+        ]
+    );
+    assert!(!options.output.join("c.txt.zstd.enc").exists());
+
+    // 预演不改变数据库，实际运行的结果与预演一致
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert_eq!(report.processed.len(), 1);
+    assert_eq!(report.deleted.len(), 1);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks_empty_dirs_and_special_files() -> Result<()> {
+    use std::os::unix::fs::symlink;
+
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    let input = &options.input;
+    create_input(input)?;
+    symlink("a.txt", input.join("link"))?;
+    symlink("missing.txt", input.join("dangling"))?;
+    fs::create_dir_all(input.join("empty").join("nested"))?;
+    let has_fifo = std::process::Command::new("mkfifo")
+        .arg(input.join("pipe"))
+        .status()
+        .is_ok_and(|status| status.success());
+
+    // 默认跳过符号链接，特殊文件写入日志
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert_eq!(report.processed.len(), 2);
+    let mut skipped: Vec<String> = report
+        .skipped
+        .iter()
+        .map(|s| s.path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    skipped.sort();
+    let mut expected = vec!["dangling", "link"];
+    if has_fifo {
+        expected.push("pipe");
+    }
+    assert_eq!(skipped, expected);
+    let logs = db.get_recent_logs_by_status(100, Some("skipped"))?;
+    assert_eq!(logs.len(), expected.len());
+    let entries: Vec<(&str, EntryKind)> = report
+        .entries
+        .iter()
+        .map(|e| (e.relative_path.as_str(), e.kind))
+        .collect();
+    assert_eq!(entries, vec![("empty/nested", EntryKind::Dir)]);
+
+    // 保存链接本身，还原时重新创建链接和空目录
+    let stored = EncryptOptions {
+        symlinks: SymlinkPolicy::Store,
+        ..options.clone()
+    };
+    let report = encrypt_tree(&mut db, &stored, |_| {})?;
+    assert!(report.processed.is_empty());
+    assert_eq!(report.summary.symlinks, 2);
+    assert_eq!(report.summary.empty_dirs, 1);
+
+    let restored = temp_dir.path().join("restored");
+    let restore_report = restore_tree(
+        &db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: restored.clone(),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert!(restore_report.failed.is_empty());
+    assert_eq!(restore_report.entries.len(), 3);
+    assert_eq!(fs::read_link(restored.join("link"))?, Path::new("a.txt"));
+    assert_eq!(fs::read(restored.join("link"))?, b"Hello, World!");
+    assert_eq!(
+        fs::read_link(restored.join("dangling"))?,
+        Path::new("missing.txt")
+    );
+    assert!(restored.join("empty").join("nested").is_dir());
+
+    // 跟随链接：链接按文件加密，失效的链接被跳过
+    let followed = EncryptOptions {
+        output: temp_dir.path().join("followed"),
+        symlinks: SymlinkPolicy::Follow,
+        ..options.clone()
+    };
+    let report = encrypt_tree(&mut db, &followed, |_| {})?;
+    assert!(report.walk_complete);
+    assert!(
+        report
+            .processed
+            .iter()
+            .any(|p| p.record.relative_path == "link")
+    );
+    assert!(report.skipped.iter().any(|s| s.path.ends_with("dangling")));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_file_names() -> Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    let name = OsStr::from_bytes(b"caf\xe9.txt");
+    fs::create_dir_all(options.input.join("sub"))?;
+    // 与 caf\xe9 有损转换后相同的文件名
+    let other = OsStr::from_bytes(b"caf\xe8.txt");
+    fs::write(options.input.join("sub").join(name), b"latin-1")?;
+    fs::write(options.input.join("sub").join(other), b"other")?;
+    fs::write(options.input.join("100%.txt"), b"percent")?;
+
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(report.failed.is_empty());
+    let mut paths: Vec<String> = report
+        .processed
+        .iter()
+        .map(|p| p.record.relative_path.clone())
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec!["100%25.txt", "sub/caf%E8.txt", "sub/caf%E9.txt"]
+    );
+
+    // 再次运行时按编码后的路径找到记录
+    let report = encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(report.processed.is_empty());
+    assert!(report.deleted.is_empty());
+
+    let restored = temp_dir.path().join("restored");
+    let restore_report = restore_tree(
+        &db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: restored.clone(),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert!(restore_report.failed.is_empty());
+    assert_eq!(fs::read(restored.join("sub").join(name))?, b"latin-1");
+    assert_eq!(fs::read(restored.join("sub").join(other))?, b"other");
+    assert_eq!(fs::read(restored.join("100%.txt"))?, b"percent");
+
+    // 附加认证数据使用无损编码，有损转换后同名的两个输出文件互换后无法解密
+    let output_of = |name: &[u8]| {
+        let mut file_name = name.to_vec();
+        file_name.extend_from_slice(b".zstd.enc");
+        options
+            .output
+            .join("sub")
+            .join(OsStr::from_bytes(&file_name))
+    };
+    let (first, second) = (output_of(b"caf\xe9.txt"), output_of(b"caf\xe8.txt"));
+    let swap = temp_dir.path().join("swap");
+    fs::rename(&first, &swap)?;
+    fs::rename(&second, &first)?;
+    fs::rename(&swap, &second)?;
+    let restore_report = restore_tree(
+        &db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: temp_dir.path().join("swapped"),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert_eq!(restore_report.failed.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_names_hides_paths() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let plain = encrypt_options(&temp_dir);
+    create_input(&plain.input)?;
+    encrypt_tree(&mut db, &plain, |_| {})?;
+    assert!(plain.output.join("a.txt.zstd.enc").is_file());
+
+    // 切换到加密文件名：已有文件重新生成到 objects/ 下，旧输出文件和明文清单被删除
+    // 对象生成时索引中已经有对应的记录，中途中断也能还原
+    let options = EncryptOptions {
+        encrypt_names: true,
+        ..plain.clone()
+    };
+    let indexed = Mutex::new(Vec::new());
+    let report = encrypt_tree(&mut db, &options, |event| {
+        if let FileEvent::Processed(file) = event {
+            let master = MasterKey::load(&options.output, "secret").unwrap().unwrap();
+            let keys = KeyRing::with_master_key("secret", master);
+            let index = load_index(&options.output, &keys).unwrap().unwrap();
+            let object_id = &file.record.object_id;
+            indexed.lock().unwrap().push(
+                index
+                    .files
+                    .iter()
+                    .any(|record| &record.object_id == object_id),
+            );
+        }
+    })?;
+    assert_eq!(indexed.into_inner().unwrap(), vec![true, true]);
+    assert!(report.encrypt_names);
+    assert_eq!(report.processed.len(), 2);
+    assert!(report.manifest_paths.is_empty());
+    assert!(options.output.join("index.hbsx").is_file());
+
+    let names: Vec<String> = walkdir::WalkDir::new(&options.output)
+        .into_iter()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert!(
+        names
+            .iter()
+            .all(|name| !name.contains("a.txt") && !name.contains("b.bin") && name != "sub")
+    );
+    for entry in walkdir::WalkDir::new(&options.output) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let content = String::from_utf8_lossy(&fs::read(entry.path())?).into_owned();
+            assert!(!content.contains("a.txt") && !content.contains("sub/b.bin"));
+        }
+    }
+    assert_eq!(
+        verify_tree(&mut db, &fast_verify(&options.output))?
+            .mismatched
+            .len(),
+        0
+    );
+
+    // 不带参数再次运行时仍使用加密文件名，修改的文件沿用原对象
+    let object_id = report
+        .processed
+        .iter()
+        .find(|p| p.record.relative_path == "a.txt")
+        .and_then(|p| p.record.object_id.clone());
+    assert!(object_id.is_some());
+    assert!(encrypt_tree(&mut db, &plain, |_| {})?.processed.is_empty());
+    fs::write(plain.input.join("a.txt"), b"changed content")?;
+    let report = encrypt_tree(&mut db, &plain, |_| {})?;
+    assert_eq!(report.processed.len(), 1);
+    assert_eq!(report.processed[0].record.object_id, object_id);
+
+    // 没有数据库记录时从加密索引还原原始路径
+    let empty_db = Database::open(&temp_dir.path().join("empty.db"))?;
+    let restored = temp_dir.path().join("restored");
+    let restore_report = restore_tree(
+        &empty_db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: restored.clone(),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert!(restore_report.failed.is_empty());
+    assert_eq!(restore_report.restored.len(), 2);
+    assert_eq!(
+        fs::read(restored.join("sub").join("b.bin"))?,
+        vec![7u8; 10_000]
+    );
+    assert_eq!(fs::read(restored.join("a.txt"))?, b"changed content");
+
+    // 索引中没有的对象无法确定原始路径，报告错误而不是按对象 ID 还原
+    let object = options.output.join(object_relative_path(&"ff".repeat(16)));
+    fs::create_dir_all(object.parent().unwrap())?;
+    fs::copy(
+        options
+            .output
+            .join(object_relative_path(object_id.as_ref().unwrap())),
+        &object,
+    )?;
+    let restore_report = restore_tree(
+        &empty_db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: temp_dir.path().join("restored-again"),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert_eq!(restore_report.restored.len(), 2);
+    assert_eq!(restore_report.failed.len(), 1);
+    assert_eq!(restore_report.failed[0].path, object);
+    assert_eq!(restore_report.failed[0].kind, RestoreFailureKind::Other);
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_names_refuses_plaintext_artifacts() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = EncryptOptions {
+        encrypt_names: true,
+        ..encrypt_options(&temp_dir)
+    };
+    create_input(&options.input)?;
+
+    // 归档目录和目录数据库中保存着明文路径，切换前需要用户自行处理
+    for name in [".archive", "catalog.db"] {
+        let path = options.output.join(name);
+        fs::create_dir_all(&options.output)?;
+        fs::write(&path, b"")?;
+        assert!(encrypt_tree(&mut db, &options, |_| {}).is_err());
+        assert!(plan_tree(&db, &options).is_err());
+        assert!(!options.output.join("index.hbsx").exists());
+        fs::remove_file(&path)?;
+    }
+
+    // 保留下来的孤立输出文件同样使用明文路径
+    let plain = EncryptOptions {
+        encrypt_names: false,
+        ..options.clone()
+    };
+    encrypt_tree(&mut db, &plain, |_| {})?;
+    fs::remove_file(options.input.join("a.txt"))?;
+    encrypt_tree(&mut db, &plain, |_| {})?;
+    let kept = options.output.join("a.txt.zstd.enc");
+    assert!(kept.is_file());
+    let error = encrypt_tree(&mut db, &options, |_| {}).unwrap_err();
+    assert!(error.to_string().contains("a.txt.zstd.enc"));
+    assert!(plan_tree(&db, &options).is_err());
+    assert!(!options.output.join("index.hbsx").exists());
+
+    fs::remove_file(&kept)?;
+    assert!(encrypt_tree(&mut db, &options, |_| {}).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_rejects_escaping_paths() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = EncryptOptions {
+        encrypt_names: true,
+        ..encrypt_options(&temp_dir)
+    };
+    fs::create_dir_all(&options.input)?;
+    fs::write(options.input.join("a.txt"), b"Hello")?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+
+    // 伪造的目录数据库：%2F 解码后的路径和含 .. 的目录项都指向还原目录之外
+    fs::remove_file(options.output.join("index.hbsx"))?;
+    db.conn
+        .execute("UPDATE files SET relative_path = '..%2Fescape.txt'", [])?;
+    let source_id = db.get_sources()?[0].id;
+    db.sync_entries(
+        source_id,
+        &[EntryRecord {
+            relative_path: "../escaped-dir".to_string(),
+            kind: EntryKind::Dir,
+            target: None,
+        }],
+        false,
+    )?;
+
+    let restored = temp_dir.path().join("restored");
+    let report = restore_tree(
+        &db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: restored.clone(),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert_eq!(report.failed.len(), 2);
+    assert!(report.restored.is_empty());
+    assert!(!temp_dir.path().join("escape.txt").exists());
+    assert!(!temp_dir.path().join("escaped-dir").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_without_catalog_strips_suffix_once() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut db = Database::open(&temp_dir.path().join("test.db"))?;
+    let options = encrypt_options(&temp_dir);
+    fs::create_dir_all(&options.input)?;
+    fs::write(options.input.join("x.zstd.enc"), b"nested")?;
+    encrypt_tree(&mut db, &options, |_| {})?;
+    assert!(options.output.join("x.zstd.enc.zstd.enc").is_file());
+
+    // 没有数据库记录时只去掉一次 .zstd.enc 后缀
+    let empty_db = Database::open(&temp_dir.path().join("empty.db"))?;
+    let restored = temp_dir.path().join("restored");
+    let report = restore_tree(
+        &empty_db,
+        &RestoreOptions {
+            input: options.output.clone(),
+            output: restored.clone(),
+            password: "secret".to_string(),
+        },
+    )?;
+    assert!(report.failed.is_empty());
+    assert_eq!(fs::read(restored.join("x.zstd.enc"))?, b"nested");
+
+    Ok(())
+}
@@ -1,9 +1,9 @@
 use anyhow::Result;
+use hbsx::db::FileRecord;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use tempfile::TempDir;
-use xor::db::FileRecord;
 
 /// 创建临时测试文件
 fn create_test_file(dir: &Path, name: &str, content: &[u8]) -> Result<PathBuf> {
@@ -70,6 +70,7 @@ fn test_compression_ratio_calculation() {
         original_size: 1000,
         output_size: 500,
         created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
     };
 
     // 压缩率应该是 50%
@@ -88,6 +89,7 @@ fn test_zero_size_compression_ratio() {
         original_size: 0,
         output_size: 0,
         created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
     };
 
     // 原始大小为 0 时应该特殊处理
@@ -111,6 +113,7 @@ fn test_high_compression_ratio() {
         original_size: 10000,
         output_size: 500,
         created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
     };
 
     // 压缩率应该是 5%
@@ -130,6 +133,7 @@ fn test_large_file_sizes() {
         original_size: 1073741824, // 1 GB
         output_size: 536870912,    // 512 MB
         created_at: "2025-12-10 10:00:00".to_string(),
+        object_id: None,
     };
 
     // 验证大小值